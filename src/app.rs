@@ -1,3 +1,6 @@
+use crate::ollama::ChatMessage;
+use std::cell::Cell;
+
 pub enum AppState {
     Input,
     Loading,
@@ -7,12 +10,22 @@ pub struct App {
     pub state: AppState,
     pub input: String,
     pub response: String,
+    // Completed turns of the current conversation, sent as history with each query
+    pub conversation: Vec<ChatMessage>,
+    // Question whose answer is currently streaming into `response`
+    pub pending_query: Option<String>,
     pub should_quit: bool,
     // Status message for indexing or other operations
     pub status: Option<String>,
     // Scroll offset for response window
     pub scroll_offset: u16,
+    // Keep the response pinned to its last line while an answer is streaming in
+    pub follow_response: bool,
+    // Largest useful scroll offset, recomputed by the UI on every render
+    pub response_max_scroll: Cell<u16>,
     pub kb_progress: Vec<String>,
+    // Advanced by the event loop's redraw tick to animate the loading indicator
+    pub spinner_frame: usize,
 }
 
 impl App {
@@ -21,10 +34,15 @@ impl App {
             state: AppState::Input,
             input: String::new(),
             response: String::new(),
+            conversation: Vec::new(),
+            pending_query: None,
             should_quit: false,
             status: None,
             scroll_offset: 0,
+            follow_response: false,
+            response_max_scroll: Cell::new(0),
             kb_progress: Vec::new(),
+            spinner_frame: 0,
         }
     }
 
@@ -36,9 +54,44 @@ impl App {
         self.state = AppState::Loading;
     }
 
+    /// Show a standalone message (e.g. an error) below the conversation
     pub fn set_response(&mut self, response: String) {
         self.response = response;
+        self.pending_query = None;
+        self.follow_response = true;
+    }
+
+    /// Begin a new turn; the answer is streamed in with `append_response`
+    pub fn start_turn(&mut self, query: String) {
+        self.pending_query = Some(query);
+        self.response.clear();
+        self.follow_response = true;
+    }
+
+    /// Move the finished question and answer into the conversation history
+    pub fn finish_turn(&mut self) {
+        if let Some(query) = self.pending_query.take() {
+            self.conversation.push(ChatMessage::user(query));
+            self.conversation.push(ChatMessage::assistant(std::mem::take(&mut self.response)));
+        }
+    }
+
+    /// Drop the in-progress turn, including any partial answer
+    pub fn cancel_turn(&mut self) {
+        self.pending_query = None;
+        self.response.clear();
+    }
+
+    pub fn new_conversation(&mut self) {
+        self.conversation.clear();
+        self.pending_query = None;
+        self.response.clear();
         self.scroll_offset = 0;
+        self.follow_response = false;
+    }
+
+    pub fn append_response(&mut self, chunk: &str) {
+        self.response.push_str(chunk);
     }
 
     pub fn set_status(&mut self, status: Option<String>) {
@@ -46,13 +99,25 @@ impl App {
     }
 
     pub fn scroll_up(&mut self) {
+        if self.follow_response {
+            // Detach from the bottom, starting from where the view currently is
+            self.follow_response = false;
+            self.scroll_offset = self.response_max_scroll.get();
+        }
         if self.scroll_offset > 0 {
             self.scroll_offset -= 1;
         }
     }
 
     pub fn scroll_down(&mut self) {
+        if self.follow_response {
+            return;
+        }
         self.scroll_offset = self.scroll_offset.saturating_add(1);
+        if matches!(self.state, AppState::Loading) && self.scroll_offset >= self.response_max_scroll.get() {
+            // Scrolled back to the bottom of a streaming answer: follow it again
+            self.follow_response = true;
+        }
     }
 
     pub fn push_char(&mut self, c: char) {
@@ -63,6 +128,12 @@ impl App {
         self.input.pop();
     }
 
+    pub fn tick(&mut self) {
+        if matches!(self.state, AppState::Loading) {
+            self.spinner_frame = self.spinner_frame.wrapping_add(1);
+        }
+    }
+
     pub fn push_kb_progress(&mut self, msg: String) {
         self.kb_progress.push(msg);
         if self.kb_progress.len() > 100 {
@@ -72,3 +143,36 @@ impl App {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finish_turn_records_question_and_answer() {
+        let mut app = App::new();
+        app.start_turn("compress a file".to_string());
+        app.append_response("use ");
+        app.append_response("gzip");
+        app.finish_turn();
+        assert_eq!(app.conversation, vec![ChatMessage::user("compress a file"), ChatMessage::assistant("use gzip")]);
+        assert!(app.pending_query.is_none());
+        assert!(app.response.is_empty());
+    }
+
+    #[test]
+    fn test_cancel_turn_and_new_conversation() {
+        let mut app = App::new();
+        app.start_turn("first".to_string());
+        app.append_response("answer");
+        app.finish_turn();
+        app.start_turn("second".to_string());
+        app.append_response("partial");
+        app.cancel_turn();
+        assert_eq!(app.conversation.len(), 2);
+        assert!(app.response.is_empty());
+
+        app.new_conversation();
+        assert!(app.conversation.is_empty());
+    }
+}
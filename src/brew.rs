@@ -1,29 +1,299 @@
 use anyhow::Result;
-use std::process::Command;
+use crate::package_source::{self, commands_in_prefix, Package, PackageCommand, PackageKind, PackageSource};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
 
-#[derive(Debug, Clone)]
-pub struct BrewPackage {
-    pub name: String,
+/// Installed formulae and casks with their metadata from `brew info --json=v2 --installed`.
+/// Falls back to plain `brew list` output (formulae only, no descriptions) if that fails.
+pub fn get_installed_packages() -> Result<Vec<Package>> {
+    match brew_stdout(&["info", "--json=v2", "--installed"]).and_then(|json| parse_info(&json)) {
+        Ok(packages) => Ok(packages),
+        Err(e) => {
+            crate::log::log_error(&format!("Failed to read brew info, falling back to brew list: {}", e));
+            get_installed_formulae()
+        }
+    }
+}
+
+fn brew_stdout(args: &[&str]) -> Result<String> {
+    package_source::command_stdout("brew", args)
 }
 
-pub fn get_installed_packages() -> Result<Vec<BrewPackage>> {
-    let output = Command::new("brew")
-        .arg("list")
-        .arg("--formula")
-        .output()?;
+/// Homebrew as a package source: formulae are inspected through their keg, casks
+/// through the binaries they link into `<prefix>/bin`
+#[derive(Default)]
+pub struct BrewSource {
+    // `brew --prefix`, looked up once per source
+    prefix: OnceLock<Option<PathBuf>>,
+}
 
-    if !output.status.success() {
-        anyhow::bail!("Failed to execute brew list command");
+impl PackageSource for BrewSource {
+    fn name(&self) -> &'static str {
+        "brew"
     }
 
-    let stdout = String::from_utf8(output.stdout)?;
-    let packages: Vec<BrewPackage> = stdout
+    fn is_available(&self) -> bool {
+        package_source::tool_available("brew", "--version")
+    }
+
+    fn installed_packages(&self) -> Result<Vec<Package>> {
+        get_installed_packages()
+    }
+
+    /// Falls back to the package name itself (man page / --help on PATH) when
+    /// the keg can't be inspected
+    fn package_commands(&self, package: &Package) -> Vec<PackageCommand> {
+        let prefix = self.prefix.get_or_init(|| match get_prefix() {
+            Ok(prefix) => Some(prefix),
+            Err(e) => {
+                crate::log::log_error(&format!("Failed to get brew prefix, indexing formula names only: {}", e));
+                None
+            }
+        });
+        let commands = prefix.as_deref().map(|prefix| package_commands(prefix, package)).unwrap_or_default();
+        if commands.is_empty() {
+            vec![PackageCommand::named(&package.name)]
+        } else {
+            commands
+        }
+    }
+}
+
+fn get_installed_formulae() -> Result<Vec<Package>> {
+    let versions = brew_stdout(&["list", "--formula", "--versions"])?;
+    // Third-party tap formulae are listed as user/repo/name
+    let full_names = brew_stdout(&["list", "--formula", "--full-name"]).unwrap_or_default();
+    Ok(parse_formulae(&versions, &full_names))
+}
+
+/// Combine `brew list --versions` ("name 1.0 1.1") with `brew list --full-name`
+fn parse_formulae(versions: &str, full_names: &str) -> Vec<Package> {
+    let taps: HashMap<&str, &str> = full_names
+        .lines()
+        .filter_map(|line| line.trim().rsplit_once('/'))
+        .map(|(tap, name)| (name, tap))
+        .collect();
+
+    versions
         .lines()
         .filter(|line| !line.trim().is_empty())
-        .map(|name| BrewPackage {
-            name: name.trim().to_string(),
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap_or_default().to_string();
+            let tap = taps.get(name.as_str()).map(|t| t.to_string()).unwrap_or_else(|| "homebrew/core".to_string());
+            // installed_on_request is unknown without brew info; every formula counts as wanted
+            Package {
+                version: parts.last().map(str::to_string),
+                tap: Some(tap),
+                kind: PackageKind::Formula,
+                ..Package::new("brew", name)
+            }
         })
+        .collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct InfoResponse {
+    #[serde(default)]
+    formulae: Vec<FormulaInfo>,
+    #[serde(default)]
+    casks: Vec<CaskInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormulaInfo {
+    name: String,
+    tap: Option<String>,
+    desc: Option<String>,
+    homepage: Option<String>,
+    #[serde(default)]
+    installed: Vec<InstalledFormula>,
+}
+
+#[derive(Debug, Deserialize)]
+struct InstalledFormula {
+    version: String,
+    #[serde(default)]
+    installed_on_request: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct CaskInfo {
+    token: String,
+    tap: Option<String>,
+    desc: Option<String>,
+    homepage: Option<String>,
+    installed: Option<String>,
+    #[serde(default)]
+    artifacts: Vec<serde_json::Value>,
+}
+
+fn parse_info(json: &str) -> Result<Vec<Package>> {
+    let response: InfoResponse = serde_json::from_str(json)?;
+    let formulae = response.formulae.into_iter().map(|formula| {
+        let installed = formula.installed.last();
+        Package {
+            version: installed.map(|i| i.version.clone()),
+            installed_on_request: installed.is_some_and(|i| i.installed_on_request),
+            kind: PackageKind::Formula,
+            tap: formula.tap,
+            desc: formula.desc,
+            homepage: formula.homepage,
+            ..Package::new("brew", formula.name)
+        }
+    });
+    // Casks can't be installed as dependencies of formulae, so they all count as requested
+    let casks = response.casks.into_iter().map(|cask| Package {
+        binaries: cask_binaries(&cask.artifacts),
+        kind: PackageKind::Cask,
+        tap: cask.tap,
+        version: cask.installed,
+        desc: cask.desc,
+        homepage: cask.homepage,
+        ..Package::new("brew", cask.token)
+    });
+    Ok(formulae.chain(casks).collect())
+}
+
+/// Names of the `binary` artifacts of a cask, e.g.
+/// `{"binary": ["{{appdir}}/Visual Studio Code.app/.../bin/code", {"target": "code"}]}`
+fn cask_binaries(artifacts: &[serde_json::Value]) -> Vec<String> {
+    artifacts
+        .iter()
+        .filter_map(|artifact| artifact.get("binary")?.as_array())
+        .filter_map(|binary| {
+            let target = binary.iter().find_map(|v| v.get("target")?.as_str());
+            let source = binary.first()?.as_str();
+            let path = target.or(source)?;
+            path.rsplit('/').next().filter(|n| !n.is_empty()).map(str::to_string)
+        })
+        .collect()
+}
+
+/// Homebrew's install prefix (`brew --prefix`), e.g. /opt/homebrew
+pub fn get_prefix() -> Result<PathBuf> {
+    Ok(PathBuf::from(brew_stdout(&["--prefix"])?.trim()))
+}
+
+/// The installed keg of a formula: `<prefix>/opt/<formula>` (which points at the
+/// linked version, and also exists for keg-only formulae), else the newest
+/// version directory under `<prefix>/Cellar/<formula>`
+pub fn keg_path(prefix: &Path, formula: &str) -> Option<PathBuf> {
+    let opt = prefix.join("opt").join(formula);
+    if opt.is_dir() {
+        return Some(opt);
+    }
+    let mut versions: Vec<PathBuf> = std::fs::read_dir(prefix.join("Cellar").join(formula))
+        .ok()?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_dir())
         .collect();
+    // Good enough for picking the newest of the rare side-by-side installs
+    versions.sort();
+    versions.pop()
+}
+
+/// Commands installed by a formula, or an empty list if its keg can't be found
+pub fn formula_commands(prefix: &Path, formula: &str) -> Vec<PackageCommand> {
+    keg_path(prefix, formula).map(|keg| commands_in_prefix(&keg)).unwrap_or_default()
+}
 
-    Ok(packages)
+/// Commands a package installs: a formula's keg contents, or a cask's linked binaries
+pub fn package_commands(prefix: &Path, package: &Package) -> Vec<PackageCommand> {
+    match package.kind {
+        PackageKind::Formula => formula_commands(prefix, &package.name),
+        PackageKind::Package => Vec::new(),
+        PackageKind::Cask => package
+            .binaries
+            .iter()
+            .map(|name| {
+                let link = prefix.join("bin").join(name);
+                PackageCommand { executable: link.exists().then_some(link), ..PackageCommand::named(name) }
+            })
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package_source::prompt_labels;
+    use crate::test_support::{touch, TempDir};
+
+    #[test]
+    fn test_formula_commands_come_from_the_keg() {
+        let prefix = TempDir::new("keg");
+        let keg = prefix.join("Cellar/coreutils/9.4");
+        touch(&keg.join("bin/gls"), 0o755);
+        touch(&keg.join("bin/gcp"), 0o755);
+        touch(&keg.join("bin/README"), 0o644);
+        touch(&keg.join("share/man/man1/gls.1"), 0o644);
+        touch(&keg.join("share/man/man8/gchroot.8.gz"), 0o644);
+        touch(&keg.join("share/man/man5/gconf.5"), 0o644);
+
+        assert_eq!(keg_path(&prefix, "coreutils"), Some(keg.clone()));
+        let commands = formula_commands(&prefix, "coreutils");
+        let names: Vec<&str> = commands.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["gchroot", "gcp", "gls"]);
+
+        let gls = &commands[2];
+        assert_eq!(gls.executable, Some(keg.join("bin/gls")));
+        assert_eq!(gls.man_page, Some(keg.join("share/man/man1/gls.1")));
+        assert!(commands[0].executable.is_none());
+        assert!(formula_commands(&prefix, "missing").is_empty());
+    }
+
+    #[test]
+    fn test_parse_formulae_with_taps_and_versions() {
+        let versions = "jq 1.7.1\npostgresql@16 16.1 16.2\nmytool 0.3\n";
+        let full_names = "jq\npostgresql@16\nacme/tools/mytool\n";
+        let packages = parse_formulae(versions, full_names);
+        assert_eq!(packages.len(), 3);
+        assert_eq!(packages[1].name, "postgresql@16");
+        assert_eq!(packages[1].version.as_deref(), Some("16.2"));
+        assert_eq!(packages[1].tap.as_deref(), Some("homebrew/core"));
+        assert_eq!(packages[2].tap.as_deref(), Some("acme/tools"));
+        assert_eq!(packages[2].kind, PackageKind::Formula);
+    }
+
+    #[test]
+    fn test_parse_info_reads_formulae_and_casks() {
+        let json = r#"{"formulae": [
+            {"name": "jq", "full_name": "jq", "tap": "homebrew/core", "desc": "Lightweight and flexible command-line JSON processor",
+             "homepage": "https://jqlang.github.io/jq/", "installed": [{"version": "1.7.1", "installed_on_request": true}]},
+            {"name": "oniguruma", "tap": "homebrew/core", "desc": "Regular expressions library", "homepage": null,
+             "installed": [{"version": "6.9.9", "installed_as_dependency": true, "installed_on_request": false}]}
+        ], "casks": [
+            {"token": "visual-studio-code", "tap": "homebrew/cask", "desc": "Open-source code editor", "homepage": "https://code.visualstudio.com/",
+             "installed": "1.85.1",
+             "artifacts": [{"app": ["Visual Studio Code.app"]},
+                           {"binary": ["{{appdir}}/Visual Studio Code.app/Contents/Resources/app/bin/code", {"target": "code"}]}]},
+            {"token": "google-cloud-sdk", "tap": "homebrew/cask", "desc": null, "installed": "460.0.0",
+             "artifacts": [{"binary": ["google-cloud-sdk/bin/gcloud"]}, {"binary": ["google-cloud-sdk/bin/gsutil"]}]},
+            {"token": "firefox", "tap": "homebrew/cask", "installed": "121.0", "artifacts": [{"app": ["Firefox.app"]}]}
+        ]}"#;
+        let packages = parse_info(json).unwrap();
+        assert_eq!(packages.len(), 5);
+
+        let jq = &packages[0];
+        assert_eq!(jq.kind, PackageKind::Formula);
+        assert_eq!(jq.version.as_deref(), Some("1.7.1"));
+        assert_eq!(jq.homepage.as_deref(), Some("https://jqlang.github.io/jq/"));
+        assert!(jq.installed_on_request);
+        assert!(!packages[1].installed_on_request);
+
+        let casks = &packages[2..];
+        assert_eq!(casks[0].kind, PackageKind::Cask);
+        assert_eq!(casks[0].version.as_deref(), Some("1.85.1"));
+        assert_eq!(casks[0].binaries, vec!["code"]);
+        assert_eq!(casks[1].binaries, vec!["gcloud", "gsutil"]);
+        assert!(casks[2].binaries.is_empty());
+
+        let labels = prompt_labels(&packages);
+        assert_eq!(labels[0], "jq (Lightweight and flexible command-line JSON processor)");
+        assert_eq!(labels[2], "google-cloud-sdk");
+        assert_eq!(labels[4], "oniguruma (Regular expressions library)");
+    }
 }
@@ -0,0 +1,268 @@
+//! Splits a cleaned man page (or --help output) into retrieval-sized chunks: one per
+//! section, plus one per option entry in sections that list options, so a query can
+//! match the single flag or example that answers it instead of the whole page.
+
+/// Longer sections are split on line boundaries into pieces of at most this size
+pub const MAX_CHUNK_CHARS: usize = 1500;
+/// Consecutive option entries shorter than this are merged into one chunk
+const MIN_OPTION_CHARS: usize = 120;
+/// A section is treated as an option list once it has this many option lines
+const MIN_OPTION_LINES: usize = 2;
+/// Sections that rarely help answer "how do I ..." questions
+const SKIPPED_SECTIONS: &[&str] = &[
+    "SEE ALSO",
+    "AUTHOR",
+    "AUTHORS",
+    "COPYRIGHT",
+    "BUGS",
+    "REPORTING BUGS",
+    "HISTORY",
+    "STANDARDS",
+    "COLOPHON",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// Section heading, e.g. "OPTIONS", with the option names appended for option
+    /// chunks ("OPTIONS --delete"). "OVERVIEW" for text before the first heading.
+    pub section: String,
+    pub content: String,
+}
+
+/// Chunk a page as produced by `indexer::get_man_page` after cleaning
+pub fn chunk_man_page(page: &str) -> Vec<Chunk> {
+    let mut chunks = Vec::new();
+    for (name, lines) in split_sections(page) {
+        if SKIPPED_SECTIONS.contains(&name.as_str()) {
+            continue;
+        }
+
+        let entries = option_entries(&lines);
+        if entries.len() < MIN_OPTION_LINES {
+            push_split(&mut chunks, &name, &lines);
+            continue;
+        }
+
+        // Prose around the options (e.g. "The options are as follows:") stays a section chunk
+        let prose: Vec<&str> = lines
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !entries.iter().any(|e| e.lines.contains(i)))
+            .map(|(_, l)| *l)
+            .collect();
+        push_split(&mut chunks, &name, &prose);
+
+        for group in merge_small(entries, &lines) {
+            let labels: Vec<&str> = group.iter().map(|e| e.label).collect();
+            let body: Vec<&str> = group.iter().flat_map(|e| e.lines.clone().map(|i| lines[i])).collect();
+            push_split(&mut chunks, &format!("{} {}", name, truncate_label(&labels.join(", "))), &body);
+        }
+    }
+    chunks
+}
+
+/// Split a page into (heading, body lines). Man-page title and footer lines are dropped.
+fn split_sections(page: &str) -> Vec<(String, Vec<&str>)> {
+    let mut sections: Vec<(String, Vec<&str>)> = vec![("OVERVIEW".to_string(), Vec::new())];
+    for line in page.lines() {
+        if is_title_line(line) {
+            continue;
+        }
+        match heading(line) {
+            Some(name) => sections.push((name, Vec::new())),
+            None => {
+                if let Some((_, body)) = sections.last_mut() {
+                    body.push(line);
+                }
+            }
+        }
+    }
+    sections.retain(|(_, body)| body.iter().any(|l| !l.trim().is_empty()));
+    sections
+}
+
+/// "NAME", "SEE ALSO" (man style) or "Options:" (help style) at column 0
+fn heading(line: &str) -> Option<String> {
+    if line.is_empty() || line.starts_with(char::is_whitespace) || line.len() > 40 {
+        return None;
+    }
+    let text = line.trim_end();
+    let is_man = text.starts_with(|c: char| c.is_ascii_uppercase())
+        && text.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || " -_/".contains(c));
+    if is_man {
+        return Some(text.to_string());
+    }
+    let name = text.strip_suffix(':')?;
+    let is_help = name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_alphabetic() || c == ' ');
+    is_help.then(|| name.to_ascii_uppercase())
+}
+
+/// "RSYNC(1)   User Commands   RSYNC(1)" and the matching footer, both at column 0
+fn is_title_line(line: &str) -> bool {
+    if line.starts_with(char::is_whitespace) {
+        return false;
+    }
+    let Some(open) = line.trim_end().strip_suffix(')').and_then(|l| l.rfind('(').map(|i| &l[i + 1..])) else {
+        return false;
+    };
+    !open.is_empty() && open.starts_with(|c: char| c.is_ascii_digit()) && open.len() <= 4
+}
+
+struct OptionEntry<'a> {
+    label: &'a str,
+    // Indexes into the section's lines
+    lines: std::ops::Range<usize>,
+}
+
+/// Find option entries: lines starting with `-` at the section's option indent, each
+/// followed by its (more deeply indented) description lines.
+fn option_entries<'a>(lines: &[&'a str]) -> Vec<OptionEntry<'a>> {
+    let is_option = |l: &str| {
+        let t = l.trim_start();
+        t.starts_with('-') && t[1..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '-')
+    };
+    let Some(indent) = lines.iter().filter(|l| is_option(l)).map(|l| indent_of(l)).min() else {
+        return Vec::new();
+    };
+
+    let mut entries: Vec<OptionEntry> = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let this_indent = indent_of(line);
+        if this_indent == indent && is_option(line) {
+            let t = line.trim();
+            // BSD/help style puts the description on the same line after a column gap
+            let label = t.split("  ").next().unwrap_or(t).trim();
+            entries.push(OptionEntry { label, lines: i..i + 1 });
+        } else if let Some(last) = entries.last_mut() {
+            if last.lines.end == i && (this_indent > indent || line.trim().is_empty()) {
+                last.lines.end = i + 1;
+            }
+        }
+    }
+    entries
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start().len()
+}
+
+/// Group consecutive entries so that each group reaches MIN_OPTION_CHARS where possible
+fn merge_small<'a>(entries: Vec<OptionEntry<'a>>, lines: &[&str]) -> Vec<Vec<OptionEntry<'a>>> {
+    let mut groups: Vec<Vec<OptionEntry>> = Vec::new();
+    let mut size = 0;
+    for entry in entries {
+        let len: usize = lines[entry.lines.clone()].iter().map(|l| l.trim().len() + 1).sum();
+        match groups.last_mut() {
+            Some(group) if size < MIN_OPTION_CHARS => {
+                group.push(entry);
+                size += len;
+            }
+            _ => {
+                groups.push(vec![entry]);
+                size = len;
+            }
+        }
+    }
+    groups
+}
+
+fn truncate_label(label: &str) -> String {
+    if label.chars().count() <= 60 {
+        label.to_string()
+    } else {
+        format!("{}...", label.chars().take(60).collect::<String>())
+    }
+}
+
+/// Push `lines` as one or more chunks of at most MAX_CHUNK_CHARS, dropping blank text
+fn push_split(chunks: &mut Vec<Chunk>, section: &str, lines: &[&str]) {
+    let mut current = String::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if !current.is_empty() && current.len() + line.len() + 1 > MAX_CHUNK_CHARS {
+            chunks.push(Chunk { section: section.to_string(), content: std::mem::take(&mut current) });
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        // A single over-long line is cut at a char boundary
+        let mut end = line.len().min(MAX_CHUNK_CHARS);
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        current.push_str(&line[..end]);
+    }
+    if !current.is_empty() {
+        chunks.push(Chunk { section: section.to_string(), content: current });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RSYNC: &str = "RSYNC(1)                 User Commands                 RSYNC(1)
+NAME
+       rsync - a fast, versatile, remote (and local) file-copying tool
+SYNOPSIS
+       rsync [OPTION...] SRC... [DEST]
+DESCRIPTION
+       Rsync is a fast and extraordinarily versatile file copying tool.
+OPTIONS
+       Rsync accepts both long (double-dash + word) and short options.
+       --verbose, -v
+              This option increases the amount of information you are given during
+              the transfer. By default, rsync works silently.
+       --delete
+              This tells rsync to delete extraneous files from the receiving side
+              (ones that aren't on the sending side), but only for the directories
+              that are being synchronized.
+EXAMPLES
+       rsync -avz --delete src/ host:dest/
+SEE ALSO
+       rsync-ssl(1), rsyncd.conf(5)
+rsync 3.2.7                       2022-10-20                     RSYNC(1)";
+
+    #[test]
+    fn test_chunks_follow_sections_and_options() {
+        let chunks = chunk_man_page(RSYNC);
+        let sections: Vec<&str> = chunks.iter().map(|c| c.section.as_str()).collect();
+        assert_eq!(
+            sections,
+            vec!["NAME", "SYNOPSIS", "DESCRIPTION", "OPTIONS", "OPTIONS --verbose, -v", "OPTIONS --delete", "EXAMPLES"]
+        );
+
+        let delete = chunks.iter().find(|c| c.section == "OPTIONS --delete").unwrap();
+        assert!(delete.content.starts_with("--delete\nThis tells rsync"));
+        assert!(delete.content.contains("being synchronized."));
+        let options = chunks.iter().find(|c| c.section == "OPTIONS").unwrap();
+        assert_eq!(options.content, "Rsync accepts both long (double-dash + word) and short options.");
+        assert!(chunks.iter().all(|c| !c.content.contains("RSYNC(1)")));
+    }
+
+    #[test]
+    fn test_bsd_and_help_style_options_are_merged_when_small() {
+        let page = "Usage: tool [-ab] file
+Options:
+  -a   all files
+  -b   brief output
+  --color=WHEN   colorize the output; WHEN can be 'always', 'auto' or 'never'. Colors are only emitted when the output is a terminal unless forced.";
+        let chunks = chunk_man_page(page);
+        assert_eq!(chunks[0], Chunk { section: "OVERVIEW".into(), content: "Usage: tool [-ab] file".into() });
+        assert_eq!(chunks[1].section, "OPTIONS -a, -b, --color=WHEN");
+        assert_eq!(chunks[1].content.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_long_sections_are_split() {
+        let line = "word ".repeat(60);
+        let page = format!("DESCRIPTION\n{}", format!("       {}\n", line).repeat(20));
+        let chunks = chunk_man_page(&page);
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|c| c.section == "DESCRIPTION" && c.content.len() <= MAX_CHUNK_CHARS));
+    }
+}
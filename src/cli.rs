@@ -0,0 +1,587 @@
+//! Command-line interface: subcommands that run without the TUI
+use anyhow::Result;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use crate::config::{self, Config};
+use crate::kb_builder::build_kb;
+use crate::ollama::OllamaClient;
+use crate::package_source::{self, PackageKind};
+use crate::rag::{RagAnswer, RagPipeline};
+use crate::vector_store::{StoredCommand, VectorStore};
+use serde::Serialize;
+use std::ffi::OsString;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+#[derive(Debug, Parser)]
+#[command(name = "mac-aid", version, about = "Find and learn the command-line tools installed on this machine")]
+pub struct Cli {
+    /// Launches the TUI when omitted
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Ask a one-shot question and print the answer
+    Ask {
+        /// Skip knowledge base retrieval and query the model directly
+        #[arg(long)]
+        no_rag: bool,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        #[arg(required = true)]
+        query: Vec<String>,
+    },
+    /// Build the knowledge base from installed packages, or update it with what changed
+    Index {
+        /// Discard the knowledge base and re-embed everything
+        #[arg(long)]
+        rebuild: bool,
+    },
+    /// Run a raw similarity search against the knowledge base
+    Search {
+        /// Number of results to show
+        #[arg(short = 'k', long, default_value_t = 5)]
+        top_k: usize,
+        /// Hide results below this cosine similarity (default: show all)
+        #[arg(long)]
+        min_score: Option<f32>,
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+        #[arg(required = true)]
+        text: Vec<String>,
+    },
+    /// Show knowledge base statistics
+    Stats,
+    /// Read or change settings in ~/.mac-aid/config.json
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Check that brew, man, Ollama and the configured models are available
+    Doctor,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigAction {
+    /// Print one setting, or all settings when no key is given
+    Get { key: Option<String> },
+    /// Change one setting
+    Set { key: String, value: String },
+}
+
+/// Parse the process arguments. For compatibility, `mac-aid <question...>` without a
+/// subcommand is treated as `mac-aid ask <question...>`.
+pub fn parse_args() -> Cli {
+    Cli::parse_from(with_implicit_ask(std::env::args_os().collect()))
+}
+
+fn with_implicit_ask(mut args: Vec<OsString>) -> Vec<OsString> {
+    let first = match args.get(1).and_then(|a| a.to_str()) {
+        Some(first) => first.to_string(),
+        None => return args,
+    };
+    let cmd = Cli::command();
+    let is_known = matches!(first.as_str(), "help" | "-h" | "--help" | "-V" | "--version")
+        || cmd.get_subcommands().any(|s| s.get_name() == first);
+    if !is_known {
+        args.insert(1, OsString::from("ask"));
+    }
+    args
+}
+
+pub async fn run(command: Command) -> Result<()> {
+    match command {
+        Command::Ask { no_rag, format, query } => ask(&query.join(" "), !no_rag, format).await,
+        Command::Index { rebuild } => index(rebuild).await,
+        Command::Search { top_k, min_score, format, text } => {
+            search(&text.join(" "), top_k, min_score.unwrap_or(f32::MIN), format).await
+        }
+        Command::Stats => stats(),
+        Command::Config { action } => config_command(action),
+        Command::Doctor => doctor().await,
+    }
+}
+
+fn ollama_from_config(cfg: &Config) -> OllamaClient {
+    let mut ollama = OllamaClient::new(cfg.ollama_model.clone());
+    ollama.set_embed_model(cfg.embedding_model.clone());
+    ollama.set_base_url(cfg.ollama_url.clone());
+    ollama
+}
+
+/// Explains that the knowledge base was embedded with `stored`, not the configured model
+fn stale_kb_message(stored: &str, configured: &str) -> String {
+    format!(
+        "Knowledge base was embedded with {} but embedding_model is {}; run `mac-aid index` to re-embed it",
+        stored, configured
+    )
+}
+
+/// Open the knowledge base only if it already exists, without creating an empty one
+fn open_existing_store() -> Result<Option<VectorStore>> {
+    let db_path = config::get_db_path()?;
+    if !db_path.exists() {
+        return Ok(None);
+    }
+    Ok(Some(VectorStore::new(db_path)?))
+}
+
+/// JSON document printed by `ask --format json`. Field names are part of the
+/// scripting interface; only add fields, never rename or remove them.
+#[derive(Debug, Serialize)]
+struct AskOutput<'a> {
+    query: &'a str,
+    answer: &'a str,
+    rag: bool,
+    results: Vec<ResultEntry>,
+    models: Models<'a>,
+    timings_ms: Timings,
+}
+
+/// JSON document printed by `search --format json`
+#[derive(Debug, Serialize)]
+struct SearchOutput<'a> {
+    query: &'a str,
+    results: Vec<ResultEntry>,
+    models: Models<'a>,
+    timings_ms: Timings,
+}
+
+#[derive(Debug, Serialize)]
+struct ResultEntry {
+    package: String,
+    command: String,
+    section: String,
+    score: f32,
+    snippet: String,
+}
+
+impl ResultEntry {
+    fn from_scored((score, cmd): &(f32, StoredCommand)) -> Self {
+        Self {
+            package: cmd.package_name.clone(),
+            command: cmd.command_name.clone(),
+            section: cmd.section.clone(),
+            score: *score,
+            snippet: snippet(&cmd.man_content, 300),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Models<'a> {
+    /// `null` for `search`
+    generation: Option<&'a str>,
+    embedding: &'a str,
+}
+
+#[derive(Debug, Serialize)]
+struct Timings {
+    /// `null` when retrieval was skipped
+    retrieval: Option<u64>,
+    /// `null` for `search`
+    generation: Option<u64>,
+    total: u64,
+}
+
+fn millis(d: Duration) -> u64 {
+    d.as_millis() as u64
+}
+
+async fn ask(query: &str, use_rag: bool, format: OutputFormat) -> Result<()> {
+    let started = Instant::now();
+    let cfg = config::load_config()?;
+    let ollama = ollama_from_config(&cfg);
+    let packages = package_source::installed_packages(&cfg.package_sources);
+    let package_names = package_source::prompt_labels(&packages);
+
+    let store = if use_rag {
+        open_existing_store().unwrap_or_else(|e| {
+            crate::log::log_error(&format!("Failed to open vector store for CLI query: {}", e));
+            None
+        })
+    } else {
+        None
+    };
+    // Retrieval against vectors from another embedding model would return noise
+    let store = match store {
+        Some(vs) => match vs.embedding_mismatch(&cfg.embedding_model)? {
+            Some(info) => {
+                eprintln!("{}", stale_kb_message(&info.model, &cfg.embedding_model));
+                None
+            }
+            None => Some(vs),
+        },
+        None => None,
+    };
+
+    let result = match &store {
+        Some(vs) => {
+            RagPipeline::new(vs, &ollama)
+                .with_min_similarity(cfg.min_similarity as f32)
+                .query_with_rag(query, &package_names, 2)
+                .await?
+        }
+        None => {
+            let generation_started = Instant::now();
+            let answer = ollama.query(query, &package_names, None).await?;
+            RagAnswer {
+                answer,
+                retrieved: Vec::new(),
+                retrieval_time: Duration::ZERO,
+                generation_time: generation_started.elapsed(),
+            }
+        }
+    };
+
+    match format {
+        OutputFormat::Text => println!("{}", result.answer),
+        OutputFormat::Json => {
+            let rag = !result.retrieved.is_empty();
+            let output = AskOutput {
+                query,
+                answer: &result.answer,
+                rag,
+                results: result.retrieved.iter().map(ResultEntry::from_scored).collect(),
+                models: Models {
+                    generation: Some(&cfg.ollama_model),
+                    embedding: &cfg.embedding_model,
+                },
+                timings_ms: Timings {
+                    retrieval: rag.then(|| millis(result.retrieval_time)),
+                    generation: Some(millis(result.generation_time)),
+                    total: millis(started.elapsed()),
+                },
+            };
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+    }
+    Ok(())
+}
+
+async fn index(rebuild: bool) -> Result<()> {
+    let cfg = config::load_config()?;
+    let db_path = config::get_db_path()?;
+
+    let packages = package_source::installed_packages(&cfg.package_sources);
+    let (status_tx, mut status_rx) = mpsc::unbounded_channel::<String>();
+    let kb_ready = Arc::new(AtomicBool::new(false));
+
+    // Without --rebuild only new or changed documentation is embedded
+    let build = build_kb(db_path, packages, status_tx, kb_ready, cfg, rebuild);
+    // Print builder progress as it happens; the channel closes when build_kb returns
+    let print = async {
+        while let Some(msg) = status_rx.recv().await {
+            println!("{}", msg);
+        }
+    };
+    let (result, _) = tokio::join!(build, print);
+    result
+}
+
+async fn search(text: &str, top_k: usize, min_score: f32, format: OutputFormat) -> Result<()> {
+    let started = Instant::now();
+    let cfg = config::load_config()?;
+    let ollama = ollama_from_config(&cfg);
+    let vs = match open_existing_store()? {
+        Some(vs) if !vs.is_empty()? => vs,
+        _ => anyhow::bail!("Knowledge base is empty; run `mac-aid index` first"),
+    };
+    if let Some(info) = vs.embedding_mismatch(&cfg.embedding_model)? {
+        anyhow::bail!("{}", stale_kb_message(&info.model, &cfg.embedding_model));
+    }
+
+    let embedding = ollama.generate_embedding(text).await?;
+    let results = vs.search_similar(&embedding, top_k, min_score)?;
+
+    match format {
+        OutputFormat::Text => {
+            for (i, (score, cmd)) in results.iter().enumerate() {
+                let section = if cmd.section.is_empty() { String::new() } else { format!(" {}", cmd.section) };
+                println!("{}. {}{} ({}) [{:.3}]", i + 1, cmd.command_name, section, cmd.package_name, score);
+                println!("   {}", snippet(&cmd.man_content, 160));
+            }
+        }
+        OutputFormat::Json => {
+            let elapsed = millis(started.elapsed());
+            let output = SearchOutput {
+                query: text,
+                results: results.iter().map(ResultEntry::from_scored).collect(),
+                models: Models {
+                    generation: None,
+                    embedding: &cfg.embedding_model,
+                },
+                timings_ms: Timings {
+                    retrieval: Some(elapsed),
+                    generation: None,
+                    total: elapsed,
+                },
+            };
+            println!("{}", serde_json::to_string_pretty(&output)?);
+        }
+    }
+    Ok(())
+}
+
+/// Extraction problems `stats` lists before summarising the rest
+const MAX_LISTED_PROBLEMS: usize = 20;
+
+fn stats() -> Result<()> {
+    let cfg = config::load_config()?;
+    let db_path = config::get_db_path()?;
+    println!("Database:         {}", db_path.display());
+
+    match open_existing_store()? {
+        Some(vs) => {
+            let size = std::fs::metadata(&db_path).map(|m| m.len()).unwrap_or(0);
+            println!("Database size:    {:.1} KiB", size as f64 / 1024.0);
+            println!("Indexed commands: {}", vs.command_count()?);
+            println!("Indexed chunks:   {}", vs.count()?);
+            println!("Indexed packages: {}", vs.package_count()?);
+            if let Some(info) = vs.embedding_info()? {
+                let dimension = info.dimension.map(|d| format!(", {} dimensions", d)).unwrap_or_default();
+                let built_at = info.built_at.map(|t| format!(", built {} UTC", t)).unwrap_or_default();
+                println!("Embedded with:    {}{}{}", info.model, dimension, built_at);
+                if info.model != cfg.embedding_model {
+                    println!("  {}", stale_kb_message(&info.model, &cfg.embedding_model));
+                }
+            }
+            let problems = vs.latest_build_problems()?;
+            if !problems.is_empty() {
+                println!("Unreadable docs:  {} commands hung or failed in the last build", problems.len());
+                for p in problems.iter().take(MAX_LISTED_PROBLEMS) {
+                    let kind = if p.timed_out { "timed out" } else { "failed" };
+                    println!("  {} ({}): {} - {}", p.command_name, p.package_name, kind, p.detail);
+                }
+                if problems.len() > MAX_LISTED_PROBLEMS {
+                    println!("  ... and {} more", problems.len() - MAX_LISTED_PROBLEMS);
+                }
+            }
+        }
+        None => println!("Indexed commands: 0 (not built yet)"),
+    }
+    if crate::kb_builder::build_in_progress(&db_path)? {
+        println!("Build:            in progress in another process");
+    } else if let Some(run) = crate::kb_builder::interrupted_build(&db_path)? {
+        println!(
+            "Unfinished build: {}/{} documents ({}); `mac-aid index` resumes it",
+            run.docs_done, run.docs_total, run.status
+        );
+    }
+
+    let packages = package_source::installed_packages(&cfg.package_sources);
+    let by_source: Vec<String> = package_source::counts_by_source(&packages)
+        .into_iter()
+        .map(|(source, n)| format!("{} {}", source, n))
+        .collect();
+    println!("Installed:        {} packages ({})", packages.len(), by_source.join(", "));
+    let brew: Vec<_> = packages.iter().filter(|p| p.source == "brew").collect();
+    if !brew.is_empty() {
+        let casks = brew.iter().filter(|p| p.kind == PackageKind::Cask).count();
+        let tapped = brew
+            .iter()
+            .filter(|p| p.tap.as_deref().is_some_and(|t| !t.starts_with("homebrew/")))
+            .count();
+        println!(
+            "  Homebrew:       {} formulae, {} casks ({} from third-party taps)",
+            brew.len() - casks,
+            casks,
+            tapped
+        );
+    }
+    println!("Generation model: {}", cfg.ollama_model);
+    println!("Embedding model:  {}", cfg.embedding_model);
+    Ok(())
+}
+
+fn config_command(action: ConfigAction) -> Result<()> {
+    let mut cfg = config::load_config()?;
+    match action {
+        ConfigAction::Get { key: Some(key) } => println!("{}", cfg.get(&key)?),
+        ConfigAction::Get { key: None } => println!("{}", serde_json::to_string_pretty(&cfg)?),
+        ConfigAction::Set { key, value } => {
+            cfg.set(&key, &value)?;
+            config::save_config(&cfg)?;
+            println!("{} = {}", key, cfg.get(&key)?);
+        }
+    }
+    Ok(())
+}
+
+async fn doctor() -> Result<()> {
+    let mut failures = 0usize;
+    let mut check = |ok: bool, msg: String| {
+        println!("{} {}", if ok { "✓" } else { "✗" }, msg);
+        if !ok {
+            failures += 1;
+        }
+    };
+
+    let cfg = config::load_config()?;
+
+    // Configured sources that aren't installed are fine, as long as one of them is
+    let available = package_source::available_sources(&cfg.package_sources);
+    for name in &cfg.package_sources {
+        if available.iter().any(|s| s.name() == name) {
+            println!("✓ Package source {} is available", name);
+        } else {
+            println!("- Package source {} is not available, skipped", name);
+        }
+    }
+    check(
+        !available.is_empty(),
+        format!("At least one package source is available ({})", cfg.package_sources.join(", ")),
+    );
+    check(package_source::tool_available("man", "-w"), "man is available".to_string());
+
+    let ollama = ollama_from_config(&cfg);
+    match ollama.list_models().await {
+        Ok(models) => {
+            check(true, format!("Ollama is reachable at {}", cfg.ollama_url));
+            for model in [&cfg.ollama_model, &cfg.embedding_model] {
+                check(
+                    has_model(&models, model),
+                    format!("Model {} is available (ollama pull {})", model, model),
+                );
+            }
+        }
+        Err(e) => check(false, format!("Ollama is reachable at {} ({})", cfg.ollama_url, e)),
+    }
+
+    match open_existing_store() {
+        Ok(Some(vs)) => {
+            let count = vs.command_count()?;
+            check(count > 0, format!("Knowledge base has {} commands", count));
+        }
+        Ok(None) => check(false, "Knowledge base exists (run `mac-aid index`)".to_string()),
+        Err(e) => check(false, format!("Knowledge base can be opened ({})", e)),
+    }
+
+    if failures > 0 {
+        anyhow::bail!("{} check(s) failed", failures);
+    }
+    Ok(())
+}
+
+/// Ollama reports untagged models as `name:latest`
+fn has_model(models: &[String], wanted: &str) -> bool {
+    models
+        .iter()
+        .any(|m| m == wanted || m.strip_suffix(":latest") == Some(wanted))
+}
+
+/// First non-empty lines of a document, collapsed to one line of at most `max_chars`
+fn snippet(text: &str, max_chars: usize) -> String {
+    let joined = text
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if joined.chars().count() <= max_chars {
+        joined
+    } else {
+        format!("{}...", joined.chars().take(max_chars).collect::<String>())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<OsString> {
+        list.iter().map(OsString::from).collect()
+    }
+
+    #[test]
+    fn test_bare_question_is_treated_as_ask() {
+        let cli = Cli::parse_from(with_implicit_ask(args(&["mac-aid", "how", "to", "zip"])));
+        match cli.command {
+            Some(Command::Ask { no_rag, format, query }) => {
+                assert!(!no_rag);
+                assert_eq!(format, OutputFormat::Text);
+                assert_eq!(query.join(" "), "how to zip");
+            }
+            other => panic!("unexpected command: {:?}", other),
+        }
+
+        let cli = Cli::parse_from(with_implicit_ask(args(&["mac-aid", "--no-rag", "zip"])));
+        assert!(matches!(cli.command, Some(Command::Ask { no_rag: true, .. })));
+    }
+
+    #[test]
+    fn test_subcommands_and_no_args() {
+        let cli = Cli::parse_from(with_implicit_ask(args(&["mac-aid", "index", "--rebuild"])));
+        assert!(matches!(cli.command, Some(Command::Index { rebuild: true })));
+
+        let cli = Cli::parse_from(with_implicit_ask(args(&["mac-aid"])));
+        assert!(cli.command.is_none());
+    }
+
+    #[test]
+    fn test_format_flag_only_for_ask_and_search() {
+        let cli = Cli::parse_from(with_implicit_ask(args(&["mac-aid", "search", "jq", "--format", "json"])));
+        assert!(matches!(cli.command, Some(Command::Search { format: OutputFormat::Json, .. })));
+        let cli = Cli::parse_from(with_implicit_ask(args(&["mac-aid", "--format", "json", "zip"])));
+        assert!(matches!(cli.command, Some(Command::Ask { format: OutputFormat::Json, .. })));
+        assert!(Cli::try_parse_from(args(&["mac-aid", "stats", "--format", "json"])).is_err());
+    }
+
+    #[test]
+    fn test_ask_output_json_shape() {
+        let retrieved = [(
+            0.5,
+            StoredCommand {
+                id: 1,
+                package_name: "jq".to_string(),
+                command_name: "jq".to_string(),
+                section: "NAME".to_string(),
+                man_content: "NAME\n  jq - JSON processor".to_string(),
+                embedding: vec![],
+            },
+        )];
+        let output = AskOutput {
+            query: "q",
+            answer: "a",
+            rag: true,
+            results: retrieved.iter().map(ResultEntry::from_scored).collect(),
+            models: Models { generation: Some("gen"), embedding: "emb" },
+            timings_ms: Timings { retrieval: Some(1), generation: Some(2), total: 3 },
+        };
+        let json = serde_json::to_value(&output).unwrap();
+        assert_eq!(json["results"][0]["command"], "jq");
+        assert_eq!(json["results"][0]["section"], "NAME");
+        assert_eq!(json["results"][0]["score"], 0.5);
+        assert_eq!(json["results"][0]["snippet"], "NAME jq - JSON processor");
+        assert_eq!(json["models"]["generation"], "gen");
+        assert_eq!(json["timings_ms"]["total"], 3);
+
+        // Fields without a value are kept as null rather than left out
+        let output = SearchOutput {
+            query: "q",
+            results: vec![],
+            models: Models { generation: None, embedding: "emb" },
+            timings_ms: Timings { retrieval: Some(1), generation: None, total: 1 },
+        };
+        let json = serde_json::to_value(&output).unwrap();
+        assert!(json["models"].as_object().unwrap()["generation"].is_null());
+        assert!(json["timings_ms"].as_object().unwrap()["generation"].is_null());
+    }
+
+    #[test]
+    fn test_has_model_accepts_latest_tag() {
+        let models = vec!["all-minilm:latest".to_string(), "qwen3:8b".to_string()];
+        assert!(has_model(&models, "all-minilm"));
+        assert!(has_model(&models, "qwen3:8b"));
+        assert!(!has_model(&models, "qwen3"));
+    }
+}
@@ -1,12 +1,43 @@
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(default)]
 pub struct Config {
     pub ollama_model: String,
     pub embedding_model: String,
     pub ollama_url: String,
+    /// Retrieved documents scoring below this cosine similarity are not used as context
+    pub min_similarity: f64,
+    /// Package managers to read the inventory from; ones that aren't installed are skipped
+    pub package_sources: Vec<String>,
+    /// Documents embedded in parallel while building the knowledge base
+    pub embed_concurrency: usize,
+    /// Chunks sent to Ollama per `/api/embed` request
+    pub embed_batch_size: usize,
+    /// Commands whose man page or help output is read in parallel while indexing
+    pub extract_workers: usize,
+    /// Seconds a single `man` or help invocation may run before it is killed
+    pub extract_timeout_secs: u64,
+    /// How stored embeddings are encoded: "f32", "f16" (half the size) or "int8" (a quarter)
+    pub embedding_encoding: crate::embedding_codec::EmbeddingEncoding,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            ollama_model: "qwen3-coder:480b-cloud".to_string(),
+            embedding_model: "all-minilm".to_string(),
+            ollama_url: "http://localhost:11434".to_string(),
+            min_similarity: 0.3,
+            package_sources: crate::package_source::DEFAULT_SOURCES.iter().map(|s| s.to_string()).collect(),
+            embed_concurrency: crate::ollama::DEFAULT_EMBED_CONCURRENCY,
+            embed_batch_size: 32,
+            extract_workers: crate::indexer::DEFAULT_EXTRACT_WORKERS,
+            extract_timeout_secs: crate::indexer::DEFAULT_EXTRACT_TIMEOUT_SECS,
+            embedding_encoding: crate::embedding_codec::EmbeddingEncoding::default(),
+        }
+    }
 }
 
 fn config_path() -> Result<std::path::PathBuf> {
@@ -16,6 +47,14 @@ fn config_path() -> Result<std::path::PathBuf> {
     Ok(dir.join("config.json"))
 }
 
+/// Location of the knowledge base database (~/.mac-aid/commands.db)
+pub fn get_db_path() -> Result<std::path::PathBuf> {
+    let home = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
+    let app_dir = home.join(".mac-aid");
+    std::fs::create_dir_all(&app_dir)?;
+    Ok(app_dir.join("commands.db"))
+}
+
 pub fn load_config() -> Result<Config> {
     let path = config_path()?;
     if path.exists() {
@@ -29,12 +68,95 @@ pub fn load_config() -> Result<Config> {
         return Ok(cfg);
     }
 
-    let default = Config {
-        ollama_model: "qwen3-coder:480b-cloud".to_string(),
-        embedding_model: "all-minilm".to_string(),
-        ollama_url: "http://localhost:11434".to_string(),
-    };
+    let default = Config::default();
     let json = serde_json::to_vec_pretty(&default)?;
     std::fs::write(path, json)?;
     Ok(default)
 }
+
+pub fn save_config(cfg: &Config) -> Result<()> {
+    let json = serde_json::to_vec_pretty(cfg)?;
+    std::fs::write(config_path()?, json)?;
+    Ok(())
+}
+
+impl Config {
+    /// Look up a single setting by its JSON key, formatted for display
+    pub fn get(&self, key: &str) -> Result<String> {
+        let value = serde_json::to_value(self)?;
+        match value.get(key) {
+            Some(serde_json::Value::String(s)) => Ok(s.clone()),
+            Some(v) => Ok(v.to_string()),
+            None => anyhow::bail!("Unknown config key: {}", key),
+        }
+    }
+
+    /// Update a single setting by its JSON key. String settings take the value
+    /// verbatim; others are parsed as JSON (numbers, booleans, lists).
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let mut json = serde_json::to_value(&*self)?;
+        let obj = json
+            .as_object_mut()
+            .ok_or_else(|| anyhow::anyhow!("Config is not a JSON object"))?;
+        let parsed = match obj.get(key) {
+            None => anyhow::bail!("Unknown config key: {}", key),
+            Some(serde_json::Value::String(_)) => serde_json::Value::String(value.to_string()),
+            Some(_) => serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(value.to_string())),
+        };
+        obj.insert(key.to_string(), parsed);
+        *self = serde_json::from_value(json)
+            .map_err(|e| anyhow::anyhow!("Invalid value for {}: {}", key, e))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_get_and_set_by_key() {
+        let mut cfg = Config::default();
+        cfg.set("ollama_model", "llama3:8b").unwrap();
+        assert_eq!(cfg.ollama_model, "llama3:8b");
+        assert_eq!(cfg.get("ollama_model").unwrap(), "llama3:8b");
+    }
+
+    #[test]
+    fn test_config_rejects_unknown_key() {
+        let mut cfg = Config::default();
+        assert!(cfg.get("nope").is_err());
+        assert!(cfg.set("nope", "x").is_err());
+    }
+
+    #[test]
+    fn test_config_package_sources_set_as_json_list() {
+        let mut cfg = Config::default();
+        assert!(cfg.package_sources.contains(&"brew".to_string()));
+        assert!(!cfg.package_sources.contains(&"path".to_string()));
+        cfg.set("package_sources", r#"["apt", "cargo"]"#).unwrap();
+        assert_eq!(cfg.package_sources, vec!["apt", "cargo"]);
+        assert!(cfg.set("package_sources", "apt").is_err());
+    }
+
+    #[test]
+    fn test_config_min_similarity_defaults_and_parses() {
+        let cfg: Config = serde_json::from_str(r#"{"ollama_model": "llama3"}"#).unwrap();
+        assert_eq!(cfg.min_similarity, 0.3);
+
+        let mut cfg = Config::default();
+        cfg.set("min_similarity", "0.45").unwrap();
+        assert_eq!(cfg.min_similarity, 0.45);
+        assert_eq!(cfg.get("min_similarity").unwrap(), "0.45");
+        assert!(cfg.set("min_similarity", "high").is_err());
+    }
+
+    #[test]
+    fn test_config_embedding_encoding_accepts_known_names() {
+        let mut cfg = Config::default();
+        assert_eq!(cfg.get("embedding_encoding").unwrap(), "f32");
+        cfg.set("embedding_encoding", "int8").unwrap();
+        assert_eq!(cfg.embedding_encoding, crate::embedding_codec::EmbeddingEncoding::Int8);
+        assert!(cfg.set("embedding_encoding", "f8").is_err());
+    }
+}
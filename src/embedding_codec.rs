@@ -0,0 +1,238 @@
+//! On-disk encoding of embeddings in `commands.embedding`. Each row also stores its
+//! vector length in `commands.dimension` and the encoding name in `commands.encoding`,
+//! so the blob itself carries no header:
+//!
+//! - `f32`: `dimension` IEEE 754 single-precision floats, little-endian (4 bytes each)
+//! - `f16`: `dimension` IEEE 754 half-precision floats, little-endian (2 bytes each)
+//! - `int8`: a little-endian f32 scale followed by `dimension` signed bytes; component
+//!   `i` is `scale * byte[i]`
+//!
+//! Databases written before this format stored `bincode::serialize(&Vec<f32>)`; they
+//! are converted to `f32` rows by the schema migration in `vector_store`.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// How embeddings are encoded when stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EmbeddingEncoding {
+    /// Exact; 4 bytes per component
+    #[default]
+    F32,
+    /// Half the size, with about three significant digits
+    F16,
+    /// A quarter of the size; searched without converting back to f32
+    Int8,
+}
+
+impl EmbeddingEncoding {
+    /// Name stored in `commands.encoding`
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::F32 => "f32",
+            Self::F16 => "f16",
+            Self::Int8 => "int8",
+        }
+    }
+
+    pub fn parse(name: &str) -> Result<Self> {
+        match name {
+            "f32" => Ok(Self::F32),
+            "f16" => Ok(Self::F16),
+            "int8" => Ok(Self::Int8),
+            other => anyhow::bail!("Unknown embedding encoding: {}", other),
+        }
+    }
+
+    /// Blob length for a vector of `dimension` components
+    fn blob_len(self, dimension: usize) -> usize {
+        match self {
+            Self::F32 => dimension * 4,
+            Self::F16 => dimension * 2,
+            Self::Int8 => 4 + dimension,
+        }
+    }
+}
+
+/// Encode `embedding` as a blob in the given encoding
+pub fn encode(encoding: EmbeddingEncoding, embedding: &[f32]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(encoding.blob_len(embedding.len()));
+    match encoding {
+        EmbeddingEncoding::F32 => {
+            for x in embedding {
+                bytes.extend_from_slice(&x.to_le_bytes());
+            }
+        }
+        EmbeddingEncoding::F16 => {
+            for x in embedding {
+                bytes.extend_from_slice(&f32_to_f16(*x).to_le_bytes());
+            }
+        }
+        EmbeddingEncoding::Int8 => {
+            let (scale, values) = quantize(embedding);
+            bytes.extend_from_slice(&scale.to_le_bytes());
+            bytes.extend(values.iter().map(|v| *v as u8));
+        }
+    }
+    bytes
+}
+
+/// Decode a blob written by `encode` back to f32 components
+pub fn decode(encoding: EmbeddingEncoding, dimension: usize, bytes: &[u8]) -> Result<Vec<f32>> {
+    check_len(encoding, dimension, bytes)?;
+    Ok(match encoding {
+        EmbeddingEncoding::F32 => bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect(),
+        EmbeddingEncoding::F16 => bytes
+            .chunks_exact(2)
+            .map(|b| f16_to_f32(u16::from_le_bytes([b[0], b[1]])))
+            .collect(),
+        EmbeddingEncoding::Int8 => {
+            let (scale, values) = split_int8(dimension, bytes)?;
+            values.iter().map(|v| scale * *v as f32).collect()
+        }
+    })
+}
+
+/// The scale and quantized components of an `int8` blob, without converting them
+pub fn split_int8(dimension: usize, bytes: &[u8]) -> Result<(f32, Vec<i8>)> {
+    check_len(EmbeddingEncoding::Int8, dimension, bytes)?;
+    let scale = f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    Ok((scale, bytes[4..].iter().map(|b| *b as i8).collect()))
+}
+
+/// Symmetric quantization: the largest component maps to ±127
+pub fn quantize(embedding: &[f32]) -> (f32, Vec<i8>) {
+    let max = embedding.iter().fold(0.0f32, |max, x| max.max(x.abs()));
+    if max == 0.0 || !max.is_finite() {
+        return (0.0, vec![0; embedding.len()]);
+    }
+    let scale = max / 127.0;
+    let values = embedding.iter().map(|x| (x / scale).round().clamp(-127.0, 127.0) as i8).collect();
+    (scale, values)
+}
+
+fn check_len(encoding: EmbeddingEncoding, dimension: usize, bytes: &[u8]) -> Result<()> {
+    let expected = encoding.blob_len(dimension);
+    if bytes.len() != expected {
+        anyhow::bail!(
+            "{} embedding of dimension {} should be {} bytes, found {}",
+            encoding.as_str(),
+            dimension,
+            expected,
+            bytes.len()
+        );
+    }
+    Ok(())
+}
+
+/// Nearest half-precision value (ties to even); out-of-range values become infinity
+fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity, or NaN (kept a NaN)
+        return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+    }
+    let half_exponent = exponent - 127 + 15;
+    if half_exponent >= 0x1f {
+        return sign | 0x7c00;
+    }
+    if half_exponent <= 0 {
+        // Subnormal in half precision, or too small even for that
+        if half_exponent < -10 {
+            return sign;
+        }
+        let full = mantissa | 0x80_0000;
+        let shift = (14 - half_exponent) as u32;
+        return sign | round_shift(full, shift) as u16;
+    }
+    // A carry out of the mantissa correctly bumps the exponent (up to infinity)
+    sign | (((half_exponent as u32) << 10) + round_shift(mantissa, 13)) as u16
+}
+
+/// `value >> shift`, rounded to nearest with ties to even
+fn round_shift(value: u32, shift: u32) -> u32 {
+    let truncated = value >> shift;
+    let rest = value & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    if rest > half || (rest == half && truncated & 1 == 1) {
+        truncated + 1
+    } else {
+        truncated
+    }
+}
+
+fn f16_to_f32(half: u16) -> f32 {
+    let sign = ((half & 0x8000) as u32) << 16;
+    let exponent = ((half >> 10) & 0x1f) as u32;
+    let mantissa = (half & 0x3ff) as u32;
+    match exponent {
+        0 => {
+            // Zero or subnormal: mantissa * 2^-24
+            let magnitude = mantissa as f32 / (1u32 << 24) as f32;
+            if sign != 0 { -magnitude } else { magnitude }
+        }
+        0x1f => f32::from_bits(sign | 0x7f80_0000 | (mantissa << 13)),
+        _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_f32_blob_is_raw_little_endian() {
+        let embedding = [1.0f32, -0.5, 0.25];
+        let bytes = encode(EmbeddingEncoding::F32, &embedding);
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(&bytes[..4], &1.0f32.to_le_bytes());
+        assert_eq!(decode(EmbeddingEncoding::F32, 3, &bytes).unwrap(), embedding);
+        assert!(decode(EmbeddingEncoding::F32, 4, &bytes).is_err());
+    }
+
+    #[test]
+    fn test_f16_round_trip() {
+        for x in [0.0f32, -0.0, 1.0, -2.5, 0.1, 65504.0, 1e-7] {
+            let back = f16_to_f32(f32_to_f16(x));
+            assert!((back - x).abs() <= x.abs() * 1e-3 + 1e-7, "{} became {}", x, back);
+        }
+        assert_eq!(f16_to_f32(f32_to_f16(f32::NEG_INFINITY)), f32::NEG_INFINITY);
+        // Smallest subnormal, and overflow to infinity
+        assert_eq!(f32_to_f16(2f32.powi(-24)), 1);
+        assert_eq!(f16_to_f32(f32_to_f16(1e6)), f32::INFINITY);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+
+        let bytes = encode(EmbeddingEncoding::F16, &[0.5, -1.0]);
+        assert_eq!(bytes.len(), 4);
+        assert_eq!(decode(EmbeddingEncoding::F16, 2, &bytes).unwrap(), vec![0.5, -1.0]);
+    }
+
+    #[test]
+    fn test_int8_round_trip_within_half_a_step() {
+        let embedding = [0.8f32, -0.4, 0.05, 0.0];
+        let bytes = encode(EmbeddingEncoding::Int8, &embedding);
+        assert_eq!(bytes.len(), 4 + 4);
+        let (scale, values) = split_int8(4, &bytes).unwrap();
+        assert_eq!(values[0], 127);
+        let back = decode(EmbeddingEncoding::Int8, 4, &bytes).unwrap();
+        for (a, b) in embedding.iter().zip(&back) {
+            assert!((a - b).abs() <= scale / 2.0 + 1e-6);
+        }
+        assert_eq!(quantize(&[0.0, 0.0]), (0.0, vec![0, 0]));
+    }
+
+    #[test]
+    fn test_encoding_names() {
+        for encoding in [EmbeddingEncoding::F32, EmbeddingEncoding::F16, EmbeddingEncoding::Int8] {
+            assert_eq!(EmbeddingEncoding::parse(encoding.as_str()).unwrap(), encoding);
+        }
+        assert!(EmbeddingEncoding::parse("f8").is_err());
+    }
+}
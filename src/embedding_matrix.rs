@@ -0,0 +1,214 @@
+use std::borrow::Cow;
+
+/// Resident copy of all stored embeddings: one contiguous, pre-normalized buffer,
+/// so an exact search is a single pass of dot products with no SQLite or decoding work.
+/// Knowledge bases stored as int8 stay quantized in memory and are searched as such.
+pub struct EmbeddingMatrix {
+    dim: usize,
+    // Row id in the commands table for each vector
+    ids: Vec<i64>,
+    rows: Rows,
+}
+
+enum Rows {
+    // Normalized vectors stored back to back (row * dim)
+    F32(Vec<f32>),
+    // Quantized vectors back to back; row i times scales[i] has unit length
+    Int8 { data: Vec<i8>, scales: Vec<f32> },
+}
+
+impl EmbeddingMatrix {
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            ids: Vec::new(),
+            rows: Rows::F32(Vec::new()),
+        }
+    }
+
+    /// A matrix that keeps its rows int8-quantized, a quarter of the memory of `new`
+    pub fn new_quantized(dim: usize) -> Self {
+        Self {
+            dim,
+            ids: Vec::new(),
+            rows: Rows::Int8 { data: Vec::new(), scales: Vec::new() },
+        }
+    }
+
+    /// Append a vector; returns false (and stores nothing) if its dimension differs
+    pub fn push(&mut self, id: i64, embedding: &[f32]) -> bool {
+        if embedding.len() != self.dim {
+            return false;
+        }
+        let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+        match &mut self.rows {
+            Rows::F32(data) if norm == 0.0 => data.extend_from_slice(embedding),
+            Rows::F32(data) => data.extend(embedding.iter().map(|x| x / norm)),
+            Rows::Int8 { data, scales } => {
+                let (scale, values) = crate::embedding_codec::quantize(embedding);
+                data.extend(values);
+                scales.push(if norm == 0.0 { scale } else { scale / norm });
+            }
+        }
+        self.ids.push(id);
+        true
+    }
+
+    /// Append a vector stored as `scale * values` (see `embedding_codec`), without
+    /// converting it to f32 if this matrix is quantized
+    pub fn push_quantized(&mut self, id: i64, scale: f32, values: &[i8]) -> bool {
+        if values.len() != self.dim {
+            return false;
+        }
+        match &mut self.rows {
+            Rows::F32(_) => {
+                let embedding: Vec<f32> = values.iter().map(|v| scale * *v as f32).collect();
+                return self.push(id, &embedding);
+            }
+            Rows::Int8 { data, scales } => {
+                let norm = scale * values.iter().map(|v| (*v as f32).powi(2)).sum::<f32>().sqrt();
+                data.extend_from_slice(values);
+                scales.push(if norm == 0.0 { scale } else { scale / norm });
+            }
+        }
+        self.ids.push(id);
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn ids(&self) -> &[i64] {
+        &self.ids
+    }
+
+    pub fn max_id(&self) -> Option<i64> {
+        self.ids.iter().copied().max()
+    }
+
+    /// Normalized vector of row `i`, converted from int8 if quantized
+    pub fn row(&self, i: usize) -> Cow<'_, [f32]> {
+        let range = i * self.dim..(i + 1) * self.dim;
+        match &self.rows {
+            Rows::F32(data) => Cow::Borrowed(&data[range]),
+            Rows::Int8 { data, scales } => Cow::Owned(data[range].iter().map(|v| scales[i] * *v as f32).collect()),
+        }
+    }
+
+    /// Dot product of row `i` with a normalized query
+    fn dot(&self, i: usize, query: &[f32]) -> f32 {
+        let range = i * self.dim..(i + 1) * self.dim;
+        match &self.rows {
+            Rows::F32(data) => data[range].iter().zip(query).map(|(a, b)| a * b).sum(),
+            Rows::Int8 { data, scales } => {
+                scales[i] * data[range].iter().zip(query).map(|(a, b)| *a as f32 * b).sum::<f32>()
+            }
+        }
+    }
+
+    /// Cosine similarity between one stored vector and a query, if the id is present
+    pub fn score(&self, id: i64, query: &[f32]) -> Option<f32> {
+        if query.len() != self.dim {
+            return None;
+        }
+        // Rows are loaded in id order
+        let row = self.ids.binary_search(&id).ok()?;
+        let norm = query.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm == 0.0 {
+            return Some(0.0);
+        }
+        Some(self.dot(row, query) / norm)
+    }
+
+    /// Exact top-k by cosine similarity, returning (score, row id) best first.
+    /// A query of the wrong dimension matches nothing.
+    pub fn search(&self, query: &[f32], top_k: usize) -> Vec<(f32, i64)> {
+        if query.len() != self.dim || top_k == 0 || self.ids.is_empty() {
+            return Vec::new();
+        }
+
+        let norm = query.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if norm == 0.0 {
+            return Vec::new();
+        }
+        let query: Vec<f32> = query.iter().map(|x| x / norm).collect();
+
+        let mut scored: Vec<(f32, i64)> = self
+            .ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| (self.dot(i, &query), id))
+            .collect();
+
+        // Partition around the k-th best, then sort just the head
+        let by_score_desc = |a: &(f32, i64), b: &(f32, i64)| b.0.total_cmp(&a.0);
+        if scored.len() > top_k {
+            scored.select_nth_unstable_by(top_k - 1, by_score_desc);
+            scored.truncate(top_k);
+        }
+        scored.sort_by(by_score_desc);
+        scored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_search_orders_by_cosine_similarity() {
+        let mut m = EmbeddingMatrix::new(2);
+        assert!(m.push(1, &[1.0, 0.0]));
+        assert!(m.push(2, &[0.0, 3.0]));
+        assert!(m.push(3, &[2.0, 2.0]));
+        assert!(!m.push(4, &[1.0, 0.0, 0.0]));
+        assert_eq!(m.len(), 3);
+
+        let res = m.search(&[0.9, 0.1], 2);
+        assert_eq!(res.iter().map(|(_, id)| *id).collect::<Vec<_>>(), vec![1, 3]);
+        assert!(res[0].0 > res[1].0);
+        assert!((m.search(&[0.0, 1.0], 1)[0].0 - 1.0).abs() < 1e-6);
+        assert!((m.score(3, &[1.0, 1.0]).unwrap() - 1.0).abs() < 1e-6);
+        assert!(m.score(4, &[1.0, 1.0]).is_none());
+    }
+
+    #[test]
+    fn test_search_rejects_mismatched_query() {
+        let mut m = EmbeddingMatrix::new(2);
+        m.push(1, &[1.0, 0.0]);
+        assert!(m.search(&[1.0, 0.0, 0.0], 1).is_empty());
+        assert!(m.search(&[0.0, 0.0], 1).is_empty());
+    }
+
+    #[test]
+    fn test_quantized_search_matches_exact_ranking() {
+        let vectors: Vec<[f32; 3]> = (0..20).map(|i| {
+            let angle = i as f32 * 0.15;
+            [angle.cos(), angle.sin(), 0.2]
+        }).collect();
+        let mut exact = EmbeddingMatrix::new(3);
+        let mut quantized = EmbeddingMatrix::new_quantized(3);
+        for (i, v) in vectors.iter().enumerate() {
+            exact.push(i as i64, v);
+            if i % 2 == 0 {
+                quantized.push(i as i64, v);
+            } else {
+                let (scale, values) = crate::embedding_codec::quantize(v);
+                assert!(quantized.push_quantized(i as i64, scale, &values));
+            }
+        }
+
+        let query = [0.7f32, 0.7, 0.2];
+        let ids = |m: &EmbeddingMatrix| m.search(&query, 5).into_iter().map(|(_, id)| id).collect::<Vec<_>>();
+        assert_eq!(ids(&quantized), ids(&exact));
+        let (a, b) = (exact.score(4, &query).unwrap(), quantized.score(4, &query).unwrap());
+        assert!((a - b).abs() < 0.01);
+        assert!((quantized.row(3).iter().map(|x| x * x).sum::<f32>() - 1.0).abs() < 0.01);
+        assert!(!quantized.push_quantized(99, 1.0, &[1, 2]));
+    }
+}
@@ -0,0 +1,328 @@
+//! Approximate nearest-neighbour index (HNSW) over normalized embeddings.
+//! Built once after the knowledge base is indexed and persisted next to commands.db,
+//! so queries only visit a few hundred vectors instead of every stored row.
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashSet};
+use std::path::Path;
+
+/// Max neighbours per node on upper layers; layer 0 keeps twice as many
+const DEFAULT_M: usize = 16;
+const DEFAULT_EF_CONSTRUCTION: usize = 100;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct HnswIndex {
+    dim: usize,
+    m: usize,
+    ef_construction: usize,
+    entry_point: Option<usize>,
+    max_level: usize,
+    // Row id in the commands table for each node
+    ids: Vec<i64>,
+    // Node vectors, normalized and stored back to back (node * dim)
+    vectors: Vec<f32>,
+    // neighbours[node][layer] for layers 0..=level of that node
+    neighbours: Vec<Vec<Vec<u32>>>,
+}
+
+/// A node together with its distance to the current query, ordered by distance
+#[derive(Clone, Copy, PartialEq)]
+struct Scored {
+    dist: f32,
+    node: usize,
+}
+
+impl Eq for Scored {}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.dist.total_cmp(&other.dist).then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl HnswIndex {
+    pub fn new(dim: usize) -> Self {
+        Self {
+            dim,
+            m: DEFAULT_M,
+            ef_construction: DEFAULT_EF_CONSTRUCTION,
+            entry_point: None,
+            max_level: 0,
+            ids: Vec::new(),
+            vectors: Vec::new(),
+            neighbours: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn dim(&self) -> usize {
+        self.dim
+    }
+
+    /// Largest row id in the index, used to detect an index left over from an older KB
+    pub fn max_id(&self) -> Option<i64> {
+        self.ids.iter().copied().max()
+    }
+
+    /// Add a vector for the given row id. Vectors with the wrong dimension are rejected.
+    pub fn insert(&mut self, id: i64, embedding: &[f32]) -> Result<()> {
+        if embedding.len() != self.dim {
+            anyhow::bail!("Embedding dimension {} does not match index dimension {}", embedding.len(), self.dim);
+        }
+
+        let node = self.ids.len();
+        let level = random_level(node, self.m);
+        self.ids.push(id);
+        self.vectors.extend(normalized(embedding));
+        self.neighbours.push(vec![Vec::new(); level + 1]);
+
+        let Some(mut entry) = self.entry_point else {
+            self.entry_point = Some(node);
+            self.max_level = level;
+            return Ok(());
+        };
+
+        let query = self.vector(node).to_vec();
+
+        // Greedy descent through the layers above the new node's level
+        for layer in (level + 1..=self.max_level).rev() {
+            entry = self.search_layer(&query, &[entry], 1, layer)[0].node;
+        }
+
+        let mut entries = vec![entry];
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let found = self.search_layer(&query, &entries, self.ef_construction, layer);
+            let selected: Vec<u32> = found.iter().take(self.m).map(|s| s.node as u32).collect();
+
+            for &other in &selected {
+                let other = other as usize;
+                self.neighbours[other][layer].push(node as u32);
+                if self.neighbours[other][layer].len() > self.max_neighbours(layer) {
+                    self.prune(other, layer);
+                }
+            }
+            self.neighbours[node][layer] = selected;
+            entries = found.iter().map(|s| s.node).collect();
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry_point = Some(node);
+        }
+        Ok(())
+    }
+
+    /// Approximate top-k search returning (cosine similarity, row id), best first.
+    /// `ef` trades speed for recall and is raised to at least `k`.
+    pub fn search(&self, query: &[f32], k: usize, ef: usize) -> Vec<(f32, i64)> {
+        let Some(mut entry) = self.entry_point else {
+            return Vec::new();
+        };
+        if query.len() != self.dim || k == 0 {
+            return Vec::new();
+        }
+
+        let query = normalized(query);
+        for layer in (1..=self.max_level).rev() {
+            entry = self.search_layer(&query, &[entry], 1, layer)[0].node;
+        }
+
+        self.search_layer(&query, &[entry], ef.max(k), 0)
+            .into_iter()
+            .take(k)
+            .map(|s| (1.0 - s.dist, self.ids[s.node]))
+            .collect()
+    }
+
+    /// Write the index to `path` atomically (temp file + rename)
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("hnsw.tmp");
+        std::fs::write(&tmp, bincode::serialize(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Ok(bincode::deserialize(&bytes)?)
+    }
+
+    fn vector(&self, node: usize) -> &[f32] {
+        &self.vectors[node * self.dim..(node + 1) * self.dim]
+    }
+
+    fn distance(&self, query: &[f32], node: usize) -> f32 {
+        let dot: f32 = query.iter().zip(self.vector(node)).map(|(a, b)| a * b).sum();
+        1.0 - dot
+    }
+
+    fn max_neighbours(&self, layer: usize) -> usize {
+        if layer == 0 { self.m * 2 } else { self.m }
+    }
+
+    /// Keep only the closest neighbours of `node` on `layer`
+    fn prune(&mut self, node: usize, layer: usize) {
+        let base = self.vector(node).to_vec();
+        let mut scored: Vec<Scored> = self.neighbours[node][layer]
+            .iter()
+            .map(|&n| Scored { dist: self.distance(&base, n as usize), node: n as usize })
+            .collect();
+        scored.sort();
+        scored.truncate(self.max_neighbours(layer));
+        self.neighbours[node][layer] = scored.into_iter().map(|s| s.node as u32).collect();
+    }
+
+    /// Best-first search on one layer; returns up to `ef` nodes sorted by distance
+    fn search_layer(&self, query: &[f32], entries: &[usize], ef: usize, layer: usize) -> Vec<Scored> {
+        let mut visited: HashSet<usize> = entries.iter().copied().collect();
+        // Min-heap of nodes to expand, max-heap of the best `ef` found so far
+        let mut candidates: BinaryHeap<std::cmp::Reverse<Scored>> = BinaryHeap::new();
+        let mut results: BinaryHeap<Scored> = BinaryHeap::new();
+
+        for &node in entries {
+            let s = Scored { dist: self.distance(query, node), node };
+            candidates.push(std::cmp::Reverse(s));
+            results.push(s);
+        }
+        while results.len() > ef {
+            results.pop();
+        }
+
+        while let Some(std::cmp::Reverse(current)) = candidates.pop() {
+            if let Some(worst) = results.peek() {
+                if current.dist > worst.dist && results.len() >= ef {
+                    break;
+                }
+            }
+            let Some(links) = self.neighbours[current.node].get(layer) else {
+                continue;
+            };
+            for &next in links {
+                let next = next as usize;
+                if !visited.insert(next) {
+                    continue;
+                }
+                let s = Scored { dist: self.distance(query, next), node: next };
+                let worst = results.peek().map(|w| w.dist).unwrap_or(f32::INFINITY);
+                if results.len() < ef || s.dist < worst {
+                    candidates.push(std::cmp::Reverse(s));
+                    results.push(s);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        results.into_sorted_vec()
+    }
+}
+
+fn normalized(v: &[f32]) -> Vec<f32> {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return v.to_vec();
+    }
+    v.iter().map(|x| x / norm).collect()
+}
+
+/// Exponentially distributed level, derived from the node number so builds are reproducible
+fn random_level(node: usize, m: usize) -> usize {
+    // splitmix64
+    let mut z = (node as u64).wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    let unit = ((z >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+    let ml = 1.0 / (m as f64).ln();
+    ((-unit.ln()) * ml).floor() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pseudo_random_vectors(n: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut state = 42u64;
+        (0..n)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        ((state >> 33) as f32 / (1u64 << 31) as f32) - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn exact_top_k(vectors: &[Vec<f32>], query: &[f32], k: usize) -> Vec<i64> {
+        let q = normalized(query);
+        let mut scored: Vec<(f32, i64)> = vectors
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (normalized(v).iter().zip(&q).map(|(a, b)| a * b).sum(), i as i64))
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.into_iter().take(k).map(|(_, id)| id).collect()
+    }
+
+    #[test]
+    fn test_search_finds_exact_match() {
+        let vectors = pseudo_random_vectors(300, 16);
+        let mut index = HnswIndex::new(16);
+        for (i, v) in vectors.iter().enumerate() {
+            index.insert(i as i64, v).unwrap();
+        }
+        assert_eq!(index.len(), 300);
+
+        let res = index.search(&vectors[123], 1, 50);
+        assert_eq!(res[0].1, 123);
+        assert!((res[0].0 - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_search_recall_against_exact_scan() {
+        let vectors = pseudo_random_vectors(1000, 24);
+        let mut index = HnswIndex::new(24);
+        for (i, v) in vectors.iter().enumerate() {
+            index.insert(i as i64, v).unwrap();
+        }
+
+        let queries = pseudo_random_vectors(1020, 24).split_off(1000);
+        let mut hits = 0;
+        for q in &queries {
+            let exact = exact_top_k(&vectors, q, 10);
+            let approx: Vec<i64> = index.search(q, 10, 64).into_iter().map(|(_, id)| id).collect();
+            hits += approx.iter().filter(|id| exact.contains(id)).count();
+        }
+        // 20 queries x 10 results; HNSW should recover the vast majority
+        assert!(hits >= 180, "recall too low: {}/200", hits);
+    }
+
+    #[test]
+    fn test_insert_rejects_wrong_dimension_and_roundtrips() {
+        let mut index = HnswIndex::new(3);
+        assert!(index.insert(1, &[1.0, 0.0]).is_err());
+        index.insert(7, &[1.0, 0.0, 0.0]).unwrap();
+        index.insert(8, &[0.0, 1.0, 0.0]).unwrap();
+
+        let dir = crate::test_support::TempDir::new("hnsw");
+        let path = dir.join("commands.hnsw");
+        index.save(&path).unwrap();
+        let loaded = HnswIndex::load(&path).unwrap();
+
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.search(&[0.1, 0.9, 0.0], 1, 10)[0].1, 8);
+    }
+}
@@ -1,5 +1,20 @@
 use anyhow::Result;
-use std::process::Command;
+use crate::package_source::{self, Package, PackageCommand, PackageSource, PATH_SOURCE};
+use futures::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::os::unix::process::ExitStatusExt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+
+/// `man` and help invocations run at once while indexing, unless configured otherwise
+pub const DEFAULT_EXTRACT_WORKERS: usize = 8;
+/// Seconds a single `man` or help invocation may run before it is killed
+pub const DEFAULT_EXTRACT_TIMEOUT_SECS: u64 = 10;
+/// Environment variables passed through to documentation commands; everything else is dropped
+const PASSED_ENV: &[&str] = &["PATH", "MANPATH", "LANG", "LC_ALL", "LC_CTYPE"];
 
 #[derive(Debug, Clone)]
 pub struct CommandDoc {
@@ -8,68 +23,279 @@ pub struct CommandDoc {
     pub man_content: String,
 }
 
+/// A command whose documentation couldn't be read because an invocation hung or broke,
+/// as opposed to the command simply having no man page or help output
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractionProblem {
+    pub package_name: String,
+    pub command_name: String,
+    pub timed_out: bool,
+    pub detail: String,
+}
+
+/// Documents read while indexing, and the commands that hung or failed
+#[derive(Debug, Default)]
+pub struct IndexReport {
+    pub docs: Vec<CommandDoc>,
+    pub problems: Vec<ExtractionProblem>,
+}
+
+/// How documentation is extracted
+#[derive(Debug, Clone, Copy)]
+pub struct ExtractOptions {
+    /// Commands read at the same time
+    pub workers: usize,
+    /// Limit for each `man` or help invocation
+    pub timeout: Duration,
+}
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self { workers: DEFAULT_EXTRACT_WORKERS, timeout: Duration::from_secs(DEFAULT_EXTRACT_TIMEOUT_SECS) }
+    }
+}
+
+/// Runs documentation commands with stdin closed, a scrubbed environment (HOME and
+/// the working directory point at a throwaway directory) and a time limit, so an
+/// interactive or misbehaving binary can't hang the build or touch the user's files
+struct Sandbox {
+    workdir: PathBuf,
+    timeout: Duration,
+}
+
+/// Outcome of one sandboxed invocation
+#[derive(Debug, PartialEq)]
+enum RunOutcome {
+    /// Exited successfully; stdout
+    Output(String),
+    /// Exited with an error, or the program doesn't exist
+    Unsuccessful,
+    TimedOut,
+    /// Couldn't be started or was killed by a signal
+    Broken(String),
+}
+
+impl Sandbox {
+    fn new(timeout: Duration) -> Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let workdir = std::env::temp_dir().join(format!("mac-aid-extract-{}-{}", std::process::id(), nanos));
+        std::fs::create_dir_all(&workdir)?;
+        Ok(Self { workdir, timeout })
+    }
+
+    fn env(&self) -> Vec<(OsString, OsString)> {
+        let mut env: Vec<(OsString, OsString)> = PASSED_ENV
+            .iter()
+            .filter_map(|name| Some((OsString::from(name), std::env::var_os(name)?)))
+            .collect();
+        let workdir = self.workdir.as_os_str().to_owned();
+        env.push(("HOME".into(), workdir.clone()));
+        env.push(("TMPDIR".into(), workdir));
+        // Plain, consistently wrapped output and no pagers waiting for a terminal
+        for (name, value) in [("MANWIDTH", "80"), ("MANPAGER", "cat"), ("PAGER", "cat"), ("TERM", "dumb"), ("NO_COLOR", "1")] {
+            env.push((name.into(), value.into()));
+        }
+        env
+    }
+
+    async fn run(&self, program: &str, args: &[&str]) -> RunOutcome {
+        // Its own process group, so a timeout also kills whatever it started
+        // (man's formatter pipeline, a wrapper script's children, ...)
+        let child = Command::new(program)
+            .args(args)
+            .env_clear()
+            .envs(self.env())
+            .current_dir(&self.workdir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn();
+        let child = match child {
+            Ok(child) => child,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return RunOutcome::Unsuccessful,
+            Err(e) => return RunOutcome::Broken(e.to_string()),
+        };
+        let group = child.id();
+
+        let output = child.wait_with_output();
+        tokio::pin!(output);
+        match tokio::time::timeout(self.timeout, &mut output).await {
+            Err(_) => {
+                // The leader isn't reaped until `output` is dropped, so the group id is still ours
+                if let Some(group) = group {
+                    // SAFETY: killpg takes no pointers; `group` is the id of the group the
+                    // child leads, which can't be reused while the leader is unreaped
+                    unsafe { libc::killpg(group as libc::pid_t, libc::SIGKILL) };
+                }
+                RunOutcome::TimedOut
+            }
+            Ok(Err(e)) => RunOutcome::Broken(e.to_string()),
+            Ok(Ok(output)) if output.status.success() => {
+                RunOutcome::Output(String::from_utf8_lossy(&output.stdout).into_owned())
+            }
+            Ok(Ok(output)) => match output.status.signal() {
+                Some(signal) => RunOutcome::Broken(format!("killed by signal {}", signal)),
+                None => RunOutcome::Unsuccessful,
+            },
+        }
+    }
+}
+
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.workdir);
+    }
+}
+
+/// What extracting one command's documentation produced
+#[derive(Debug, PartialEq)]
+enum Extraction {
+    Found(String),
+    /// No man page and no help output
+    Missing,
+    /// Hung or broke before any documentation was found
+    Problem { timed_out: bool, detail: String },
+}
+
 /// Extract man page content for a given command
 /// Falls back to command help options if man page is not available
-pub fn get_man_page(command: &str) -> Result<String> {
+async fn get_man_page(sandbox: &Sandbox, command: &str, help_options: &[&str]) -> Extraction {
+    get_documentation(sandbox, command, command, help_options).await
+}
+
+/// Render `man_topic` (a command name or a path to a man page file), falling back
+/// to running `program` (a name on PATH or a path) with each of `help_options`
+async fn get_documentation(sandbox: &Sandbox, man_topic: &str, program: &str, help_options: &[&str]) -> Extraction {
+    let mut problem: Option<(bool, String)> = None;
+
     // Try man page first
-    let output = Command::new("man")
-        .arg(command)
-        .env("MANWIDTH", "80") // Set consistent width for parsing
-        .output()?;
-
-    if output.status.success() {
-        let content = String::from_utf8(output.stdout)?;
-        return Ok(content);
+    match sandbox.run("man", &[man_topic]).await {
+        RunOutcome::Output(content) if !content.trim().is_empty() => return Extraction::Found(content),
+        RunOutcome::TimedOut => problem = Some((true, format!("man {} timed out", man_topic))),
+        RunOutcome::Broken(e) => problem = Some((false, format!("man {}: {}", man_topic, e))),
+        _ => {}
     }
 
-    // Fallback: try help options in order: -h, --help, -help
-    let help_options = vec!["-h", "--help", "-help"];
+    // Fallback: try help options in order (e.g. -h, --help, -help)
     for option in help_options {
-        if let Ok(output) = Command::new(command)
-            .arg(option)
-            .output()
-        {
-            if output.status.success() {
-                if let Ok(content) = String::from_utf8(output.stdout) {
-                    if !content.trim().is_empty() {
-                        return Ok(content);
-                    }
-                }
+        match sandbox.run(program, &[option]).await {
+            RunOutcome::Output(content) if !content.trim().is_empty() => return Extraction::Found(content),
+            // A program that hangs on one help option would likely hang on the others too
+            RunOutcome::TimedOut => {
+                problem = Some((true, format!("{} {} timed out", program, option)));
+                break;
+            }
+            RunOutcome::Broken(e) => {
+                problem = Some((false, format!("{} {}: {}", program, option, e)));
+                break;
             }
+            _ => {}
         }
     }
 
-    // If all attempts fail, return error
-    anyhow::bail!("Failed to get man page or help for: {}", command);
+    match problem {
+        Some((timed_out, detail)) => Extraction::Problem { timed_out, detail },
+        None => Extraction::Missing,
+    }
 }
 
-/// Index all brew packages and their man pages
-pub async fn index_brew_packages(packages: &[String]) -> Result<Vec<CommandDoc>> {
-    let mut docs = Vec::new();
-    
-    for package in packages {
-        // Try to get man page for the package
-        match get_man_page(package) {
-            Ok(content) => {
-                // Clean up the content (remove ANSI codes, etc.)
-                let cleaned = clean_man_content(&content);
-                
-                docs.push(CommandDoc {
-                    package_name: package.clone(),
-                    command_name: package.clone(),
-                    man_content: cleaned,
+/// Documentation for a command a package installs. The package's own files are used
+/// when known, so e.g. keg-only formulae (not on PATH or MANPATH) are covered too.
+async fn get_package_command_doc(sandbox: &Sandbox, cmd: &PackageCommand) -> Extraction {
+    if cmd.executable.is_none() && cmd.man_page.is_none() {
+        return get_man_page(sandbox, &cmd.name, cmd.help_options).await;
+    }
+    let program = cmd
+        .executable
+        .as_deref()
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or_else(|| cmd.name.clone());
+    // A path makes man read that exact file instead of searching MANPATH
+    let man_topic = match &cmd.man_page {
+        Some(page) => page.to_string_lossy().into_owned(),
+        None => cmd.name.clone(),
+    };
+    get_documentation(sandbox, &man_topic, &program, cmd.help_options).await
+}
+
+/// Index installed packages: one document per command each package installs, with
+/// commands found by the package's own source (brew keg, dpkg file list, ...).
+/// Executables found by the PATH scan are only indexed when no package installs them.
+/// Packages named in `already_indexed` are skipped; their commands still count as packaged.
+/// Documentation is read by `options.workers` sandboxed workers at a time.
+pub async fn index_packages(
+    packages: &[Package],
+    already_indexed: &HashSet<String>,
+    options: ExtractOptions,
+) -> Result<IndexReport> {
+    // One instance per source so per-source lookups (brew --prefix, ...) are cached
+    let mut sources: HashMap<String, Option<Box<dyn PackageSource>>> = HashMap::new();
+    // Commands of real packages, so the PATH scan (handled last) doesn't index them again
+    let mut packaged: HashSet<String> = HashSet::new();
+    let (path_dirs, managed): (Vec<&Package>, Vec<&Package>) =
+        packages.iter().partition(|p| p.source == PATH_SOURCE);
+
+    // Decide what to read first (this only lists files), then read it in parallel
+    let mut jobs: Vec<(&Package, PackageCommand)> = Vec::new();
+    for package in managed.into_iter().chain(path_dirs) {
+        let source = sources
+            .entry(package.source.clone())
+            .or_insert_with(|| package_source::source_by_name(&package.source));
+        let commands = match source {
+            Some(source) => source.package_commands(package),
+            None => vec![PackageCommand::named(&package.name)],
+        };
+
+        for cmd in commands {
+            if package.source != PATH_SOURCE {
+                packaged.insert(cmd.name.clone());
+            } else if packaged.contains(&cmd.name) {
+                continue;
+            }
+            if already_indexed.contains(&package.name) {
+                continue;
+            }
+            jobs.push((package, cmd));
+        }
+    }
+
+    let sandbox = Sandbox::new(options.timeout)?;
+    let sandbox = &sandbox;
+    let extractions: Vec<Extraction> = futures::stream::iter(jobs.iter().map(|(_, cmd)| get_package_command_doc(sandbox, cmd)))
+        .buffered(options.workers.max(1))
+        .collect()
+        .await;
+
+    let mut report = IndexReport::default();
+    for ((package, cmd), extraction) in jobs.iter().zip(extractions) {
+        match extraction {
+            Extraction::Found(content) => {
+                report.docs.push(CommandDoc {
+                    package_name: package.name.clone(),
+                    command_name: cmd.name.clone(),
+                    man_content: clean_man_content(&content),
                 });
-                
-                crate::log::log_info(&format!("Indexed: {}", package));
+                crate::log::log_info(&format!("Indexed: {} ({} {})", cmd.name, package.source, package.name));
             }
-            Err(_) => {
-                // Some packages don't have man pages, skip them
-                crate::log::log_info(&format!("Indexed: {}", package));
+            Extraction::Missing => {
+                // Some commands don't have man pages or help output, skip them
+                crate::log::log_info(&format!("No documentation for: {} ({} {})", cmd.name, package.source, package.name));
+            }
+            Extraction::Problem { timed_out, detail } => {
+                crate::log::log_error(&format!("Failed to read documentation for {} ({} {}): {}", cmd.name, package.source, package.name, detail));
+                report.problems.push(ExtractionProblem {
+                    package_name: package.name.clone(),
+                    command_name: cmd.name.clone(),
+                    timed_out,
+                    detail,
+                });
             }
         }
     }
-    
-    Ok(docs)
+
+    Ok(report)
 }
 
 /// Clean man page content by removing ANSI escape codes and extra whitespace
@@ -95,6 +321,8 @@ fn clean_man_content(content: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::package_source::HELP_OPTIONS;
+    use crate::test_support::{script, TempDir};
 
     #[test]
     fn test_clean_man_content_removes_ansi_and_empty_lines() {
@@ -109,4 +337,47 @@ mod tests {
         let out = clean_man_content(s);
         assert!(!out.contains('\x08'));
     }
+
+    #[tokio::test]
+    async fn test_sandbox_closes_stdin_and_scrubs_environment() {
+        let sandbox = Sandbox::new(Duration::from_secs(5)).unwrap();
+        // cat would wait forever on an inherited terminal
+        assert_eq!(sandbox.run("cat", &[]).await, RunOutcome::Output(String::new()));
+
+        let bin = TempDir::new("bin");
+        let env = script(&bin, "env-check", r#"echo "$HOME|$(pwd)|$PAGER|${CARGO_PKG_NAME:-scrubbed}""#);
+        let expected = format!("{0}|{0}|cat|scrubbed\n", sandbox.workdir.display());
+        assert_eq!(sandbox.run(env.to_str().unwrap(), &[]).await, RunOutcome::Output(expected));
+        assert_eq!(sandbox.run("mac-aid-no-such-command", &["-h"]).await, RunOutcome::Unsuccessful);
+    }
+
+    #[tokio::test]
+    async fn test_hanging_help_is_reported_as_timed_out() {
+        let sandbox = Sandbox::new(Duration::from_millis(300)).unwrap();
+        let bin = TempDir::new("bin");
+        // The script forks a grandchild that would outlive a kill of the script alone
+        let pid_file = bin.join("grandchild.pid");
+        let hang = script(&bin, "mac-aid-test-hang", &format!("sleep 30 &\necho $! > {}\nwait", pid_file.display()));
+        let started = std::time::Instant::now();
+        let extraction = get_documentation(&sandbox, "mac-aid-test-hang", hang.to_str().unwrap(), HELP_OPTIONS).await;
+        assert!(matches!(extraction, Extraction::Problem { timed_out: true, .. }));
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        let grandchild = std::fs::read_to_string(&pid_file).unwrap().trim().to_string();
+        let alive = || {
+            // ps works on macOS and Linux; a zombie waiting to be reaped by init counts as gone
+            let output = std::process::Command::new("ps").args(["-p", &grandchild, "-o", "stat="]).output().unwrap();
+            let stat = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            output.status.success() && !stat.is_empty() && !stat.starts_with('Z')
+        };
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while alive() && std::time::Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(!alive(), "grandchild {} survived the timeout", grandchild);
+
+        let help = script(&bin, "mac-aid-test-help", r#"[ "$1" = "--help" ] && echo "usage: help" || exit 1"#);
+        let extraction = get_documentation(&sandbox, "mac-aid-test-help", help.to_str().unwrap(), HELP_OPTIONS).await;
+        assert_eq!(extraction, Extraction::Found("usage: help\n".to_string()));
+    }
 }
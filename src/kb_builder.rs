@@ -1,74 +1,444 @@
 use anyhow::Result;
+use futures::StreamExt;
+use crate::chunker;
+use crate::embedding_codec::EmbeddingEncoding;
 use crate::indexer;
 use crate::ollama::OllamaClient;
-use crate::vector_store::VectorStore;
+use crate::package_source::Package;
+use crate::vector_store::{self, BuildRun, VectorStore};
 use crate::log;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use crate::config::Config;
 use tokio::sync::mpsc::UnboundedSender;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
-use std::time::Duration;
 
+/// A progress message is sent every this many documents
+const STATUS_EVERY: usize = 5;
+
+/// How documentation is read and sent to the embedding model
+#[derive(Clone, Copy)]
+struct BuildSettings {
+    /// Workers and time limit for reading man pages and help output
+    extract: indexer::ExtractOptions,
+    /// Documents embedded at the same time
+    concurrency: usize,
+    /// Chunks sent per `/api/embed` request
+    batch_size: usize,
+    /// How embeddings are stored
+    encoding: EmbeddingEncoding,
+}
+
+/// Counts reported when a build finishes
+struct BuildSummary {
+    embedded: usize,
+    up_to_date: usize,
+    removed: usize,
+    /// Commands whose documentation extraction hung or failed
+    problems: usize,
+}
+
+/// The shadow database a build writes to before it is swapped in
+fn shadow_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("next.db")
+}
+
+/// Held by the process running a build, for the whole of `build_kb`, so no other
+/// process resumes, replaces or abandons the shadow database it is writing. The OS
+/// releases it when the process exits, however it exits.
+struct BuildLock {
+    _file: std::fs::File,
+}
+
+impl BuildLock {
+    /// The lock for the knowledge base at `db_path`, unless another build holds it
+    fn try_acquire(db_path: &Path) -> Result<Option<Self>> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(db_path.with_extension("next.db.lock"))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(Self { _file: file })),
+            Err(std::fs::TryLockError::WouldBlock) => Ok(None),
+            Err(std::fs::TryLockError::Error(e)) => Err(e.into()),
+        }
+    }
+}
+
+/// Whether another build of the knowledge base at `db_path` is running right now
+pub fn build_in_progress(db_path: &Path) -> Result<bool> {
+    Ok(BuildLock::try_acquire(db_path)?.is_none())
+}
+
+/// A build that stopped before finishing (the app was quit, or it failed) and left its
+/// shadow database behind, so `build_kb` can continue it. A build that is still
+/// running elsewhere isn't interrupted (see `build_in_progress`).
+pub fn interrupted_build(db_path: &Path) -> Result<Option<BuildRun>> {
+    match BuildLock::try_acquire(db_path)? {
+        Some(_lock) => unfinished_run(db_path),
+        None => Ok(None),
+    }
+}
+
+/// The unfinished run recorded for the shadow database, if it still exists
+fn unfinished_run(db_path: &Path) -> Result<Option<BuildRun>> {
+    if !shadow_path(db_path).exists() {
+        return Ok(None);
+    }
+    VectorStore::new(db_path.to_path_buf())?.incomplete_build_run()
+}
+
+/// Whether the shadow database was started with `model`, so a resumed build doesn't mix
+/// vectors from two models
+fn shadow_embeds_with(shadow_path: &Path, model: &str) -> Result<bool> {
+    Ok(VectorStore::new(shadow_path.to_path_buf())?.embedding_mismatch(model)?.is_none())
+}
+
+/// Build or update the knowledge base at `db_path`. The build works on a shadow copy
+/// of the database that is swapped in only once it has finished, so the live knowledge
+/// base stays queryable throughout and is left untouched if the build fails. With
+/// `full`, the shadow starts empty instead of from the current contents.
+///
+/// An interrupted build is resumed from its shadow: every document it stored is kept
+/// (see the per-command hashes), so only the rest is extracted and embedded.
 pub async fn build_kb(
     db_path: PathBuf,
-    packages: Vec<crate::brew::BrewPackage>,
+    packages: Vec<Package>,
     status_tx: UnboundedSender<String>,
     kb_ready: Arc<std::sync::atomic::AtomicBool>,
     cfg: Config,
+    full: bool,
 ) -> Result<()> {
     // Create a local Ollama client for embedding/generation
     let mut ollama = OllamaClient::new(cfg.ollama_model);
     ollama.set_embed_model(cfg.embedding_model);
     ollama.set_base_url(cfg.ollama_url);
+    ollama.set_embed_concurrency(cfg.embed_concurrency);
+    let settings = BuildSettings {
+        extract: indexer::ExtractOptions {
+            workers: cfg.extract_workers.max(1),
+            timeout: std::time::Duration::from_secs(cfg.extract_timeout_secs.max(1)),
+        },
+        concurrency: cfg.embed_concurrency.max(1),
+        batch_size: cfg.embed_batch_size.max(1),
+        encoding: cfg.embedding_encoding,
+    };
+
+    // Open (or create) the live store in this task, and the shadow the build writes to.
+    // Builds are incremental: what the store already has for unchanged packages and
+    // documentation is carried over.
+    let Some(_lock) = BuildLock::try_acquire(&db_path)? else {
+        anyhow::bail!("A knowledge base build is already in progress");
+    };
+    let live = VectorStore::new(db_path.clone())?;
+    let shadow_path = shadow_path(&db_path);
+    // Vectors from another embedding model can't be reused, so everything is re-embedded
+    let mut full = full;
+    if let Some(info) = live.embedding_mismatch(ollama.embed_model())? {
+        let _ = status_tx.send(format!(
+            "Knowledge base was embedded with {}, not {}; re-embedding everything",
+            info.model,
+            ollama.embed_model()
+        ));
+        full = true;
+    }
+    let run_id = match unfinished_run(&db_path)? {
+        Some(run) if (!full || run.full) && shadow_embeds_with(&shadow_path, ollama.embed_model())? => {
+            let _ = status_tx.send(format!(
+                "Resuming interrupted build ({}/{} documents were done)",
+                run.docs_done, run.docs_total
+            ));
+            live.set_build_run_status(run.id, "running")?;
+            run.id
+        }
+        _ => {
+            if full {
+                vector_store::remove_store_files(&shadow_path);
+            } else {
+                live.snapshot_to(&shadow_path)?;
+            }
+            live.start_build_run(full)?
+        }
+    };
+
+    let summary = match update_shadow(&live, run_id, &shadow_path, &packages, &ollama, settings, &status_tx).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            // The shadow is kept so the next build resumes from it
+            let _ = live.set_build_run_status(run_id, "failed");
+            return Err(e);
+        }
+    };
+
+    // Publish the new knowledge base in one step
+    live.swap_in(&shadow_path)?;
+    live.set_build_run_status(run_id, "complete")?;
+    vector_store::remove_store_files(&shadow_path);
+
+    // Final count
+    let _ = status_tx.send(format!(
+        "Knowledge base built: {} commands indexed ({} chunks); {} embedded, {} already up to date, {} packages removed.",
+        live.command_count()?,
+        live.count()?,
+        summary.embedded,
+        summary.up_to_date,
+        summary.removed
+    ));
+    if summary.problems > 0 {
+        let _ = status_tx.send(format!(
+            "{} commands hung or failed while reading their documentation; see `mac-aid stats`",
+            summary.problems
+        ));
+    }
+
+    // mark ready
+    kb_ready.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Bring the shadow database up to date with `packages`, recording progress in the
+/// live store's build run after every document
+async fn update_shadow(
+    live: &VectorStore,
+    run_id: i64,
+    shadow_path: &Path,
+    packages: &[Package],
+    ollama: &OllamaClient,
+    settings: BuildSettings,
+    status_tx: &UnboundedSender<String>,
+) -> Result<BuildSummary> {
+    let mut vs = VectorStore::new(shadow_path.to_path_buf())?;
+    vs.set_embedding_model(ollama.embed_model())?;
+    // Vectors kept from the last build follow a change of `embedding_encoding` too
+    vs.set_embedding_encoding(settings.encoding);
+    let converted = vs.convert_embeddings()?;
+    if converted > 0 {
+        let _ = status_tx.send(format!("Re-encoded {} stored embeddings as {}", converted, settings.encoding.as_str()));
+    }
 
-    // Open (or create) the vector store in this task
-    let mut vs = VectorStore::new(db_path.clone())?;
-    let _ = vs.clear();
+    // Keep package metadata next to the commands for retrieval
+    if let Err(e) = vs.store_packages(packages) {
+        log::log_error(&format!("Failed to store package metadata: {}", e));
+    }
+    let descriptions: HashMap<&str, &str> = packages
+        .iter()
+        .filter_map(|p| Some((p.name.as_str(), p.desc.as_deref()?)))
+        .collect();
+
+    // Uninstalled packages take their commands with them
+    let installed: HashSet<&str> = packages.iter().map(|p| p.name.as_str()).collect();
+    let removed = vs.remove_packages_except(&installed)?;
+
+    // Packages still at the version that was indexed aren't read again. Packages
+    // without a version are always read and rely on the hashes below.
+    let indexed_versions = vs.indexed_versions()?;
+    let unchanged: HashSet<String> = packages
+        .iter()
+        .filter(|p| p.version.is_some() && p.version.as_ref() == indexed_versions.get(&p.name))
+        .map(|p| p.name.clone())
+        .collect();
+    let _ = status_tx.send(format!("{} of {} packages unchanged since the last build", unchanged.len(), packages.len()));
 
     // Index packages
-    let package_names: Vec<String> = packages.iter().map(|p| p.name.clone()).collect();
-    let docs = indexer::index_brew_packages(&package_names).await?;
+    let report = indexer::index_packages(packages, &unchanged, settings.extract).await?;
+    live.record_build_problems(run_id, &report.problems)?;
+    let docs = report.docs;
     let total = docs.len();
+    let versions: HashMap<&str, &str> = packages
+        .iter()
+        .filter_map(|p| Some((p.name.as_str(), p.version.as_deref()?)))
+        .collect();
 
-    // Process docs in batches
-    let batch_size = 5;
-    for batch_start in (0..total).step_by(batch_size) {
-        let batch_end = std::cmp::min(batch_start + batch_size, total);
-        let batch = &docs[batch_start..batch_end];
-
-        for doc in batch {
-            match ollama.generate_embedding(&doc.man_content).await {
-                Ok(embedding) => {
-                    if let Err(e) = vs.store_command(
-                        &doc.package_name,
-                        &doc.command_name,
-                        &doc.man_content,
-                        &embedding,
-                    ) {
+    // Commands a re-read package no longer has (e.g. dropped in an upgrade)
+    let mut found: HashMap<&str, HashSet<String>> = packages
+        .iter()
+        .filter(|p| !unchanged.contains(&p.name))
+        .map(|p| (p.name.as_str(), HashSet::new()))
+        .collect();
+    for doc in &docs {
+        found.entry(doc.package_name.as_str()).or_default().insert(doc.command_name.clone());
+    }
+    // A command that hung this time keeps what an earlier build stored for it
+    for problem in &report.problems {
+        found.entry(problem.package_name.as_str()).or_default().insert(problem.command_name.clone());
+    }
+    for (package, commands) in &found {
+        vs.remove_commands_except(package, commands)?;
+    }
+
+    // A package is marked indexed as soon as its last document is stored, so a resumed
+    // build skips it. Packages without any documents are done already.
+    let mut remaining: HashMap<&str, usize> = found.keys().map(|package| (*package, 0)).collect();
+    for doc in &docs {
+        *remaining.entry(doc.package_name.as_str()).or_default() += 1;
+    }
+    // Packages with a command that couldn't be read aren't marked, so the next build retries them
+    let mut failed: HashSet<&str> = report.problems.iter().map(|p| p.package_name.as_str()).collect();
+    for (package, left) in &remaining {
+        if failed.contains(package) {
+            continue;
+        }
+        if let (0, Some(version)) = (left, versions.get(package)) {
+            vs.mark_package_indexed(package, version)?;
+        }
+    }
+
+    let hashes = vs.command_hashes()?;
+    let mut done = 0usize;
+    let mut embedded = 0usize;
+    let mut up_to_date = 0usize;
+    // Taken from the first vectors this build embeds; kept from the last build otherwise
+    let mut dimension: Option<usize> = None;
+
+    // Each section and option of a page is embedded and stored on its own. Documents are
+    // embedded `concurrency` at a time and stored here as they finish, in any order.
+    let jobs = docs.iter().map(|doc| {
+        let desc = descriptions.get(doc.package_name.as_str()).copied();
+        let hashes = &hashes;
+        async move {
+            let chunks: Vec<(chunker::Chunk, String)> = chunker::chunk_man_page(&doc.man_content)
+                .into_iter()
+                .map(|chunk| {
+                    let text = embedding_text(&doc.command_name, desc, &chunk);
+                    (chunk, text)
+                })
+                .collect();
+            let hash = content_hash(chunks.iter().map(|(_, text)| text.as_str()));
+            if hashes.get(&(doc.package_name.clone(), doc.command_name.clone())) == Some(&hash) {
+                return (doc, hash, Ok(None));
+            }
+            let result = embed_chunks(ollama, &doc.command_name, chunks, settings.batch_size).await;
+            (doc, hash, result.map(Some))
+        }
+    });
+    let mut results = futures::stream::iter(jobs).buffer_unordered(settings.concurrency);
+
+    while let Some((doc, hash, result)) = results.next().await {
+        match result {
+            Ok(None) => up_to_date += 1,
+            Ok(Some(embedded_chunks)) => {
+                if let Some((_, embedding)) = embedded_chunks.first() {
+                    dimension.get_or_insert(embedding.len());
+                }
+                match vs.replace_command(&doc.package_name, &doc.command_name, &hash, &embedded_chunks) {
+                    Ok(()) => embedded += 1,
+                    Err(e) => {
+                        failed.insert(&doc.package_name);
                         let _ = status_tx.send(format!("Failed to store: {}: {}", doc.command_name, e));
                         log::log_error(&format!("Failed to store during build fallback: {}: {}", doc.command_name, e));
                     }
                 }
-                Err(e) => {
-                    let _ = status_tx.send(format!("Failed to embed: {}: {}", doc.command_name, e));
-                    log::log_error(&format!("Failed to embed during build fallback: {}: {}", doc.command_name, e));
-                }
             }
+            Err(e) => {
+                // The command's previous chunks, if any, stay until it embeds successfully
+                failed.insert(&doc.package_name);
+                let _ = status_tx.send(format!("Failed to embed: {}", e));
+                log::log_error(&format!("Failed to embed during build fallback: {}", e));
+            }
+        }
+
+        // Packages with a failed command aren't marked, so the next build retries them
+        let left = remaining.entry(doc.package_name.as_str()).or_default();
+        *left = left.saturating_sub(1);
+        if *left == 0 && !failed.contains(doc.package_name.as_str()) {
+            if let Some(version) = versions.get(doc.package_name.as_str()) {
+                vs.mark_package_indexed(&doc.package_name, version)?;
+            }
+        }
+        done += 1;
+        live.checkpoint_build_run(run_id, done, total, &format!("{}/{}", doc.package_name, doc.command_name))?;
+        if done.is_multiple_of(STATUS_EVERY) || done == total {
+            let _ = status_tx.send(format!("Indexed {}/{} commands", done, total));
         }
+    }
 
+    // Build the approximate nearest-neighbour index used by search_similar
+    let _ = status_tx.send("Building search index...".to_string());
+    if let Err(e) = vs.build_ann_index() {
+        let _ = status_tx.send(format!("Failed to build search index: {}", e));
+        log::log_error(&format!("Failed to build HNSW index: {}", e));
+    }
+
+    let dimension = match dimension {
+        Some(dimension) => Some(dimension),
+        None => vs.embedding_info()?.and_then(|info| info.dimension),
+    };
+    vs.record_embedding_info(ollama.embed_model(), dimension)?;
 
-        // Update status and sleep a bit to yield
-        let _ = status_tx.send(format!("Indexed {}/{} commands", batch_end, total));
-        tokio::time::sleep(Duration::from_millis(50)).await;
+    Ok(BuildSummary { embedded, up_to_date, removed, problems: report.problems.len() })
+}
+
+/// Embed every chunk of a command, `batch_size` chunks per request; fails if any
+/// request fails
+async fn embed_chunks(
+    ollama: &OllamaClient,
+    command_name: &str,
+    chunks: Vec<(chunker::Chunk, String)>,
+    batch_size: usize,
+) -> Result<Vec<(chunker::Chunk, Vec<f32>)>> {
+    let (chunks, texts): (Vec<chunker::Chunk>, Vec<String>) = chunks.into_iter().unzip();
+    let mut embeddings = Vec::with_capacity(texts.len());
+    for batch in texts.chunks(batch_size) {
+        let batch_embeddings = ollama
+            .generate_embeddings(batch)
+            .await
+            .map_err(|e| anyhow::anyhow!("{}: {}", command_name, e))?;
+        embeddings.extend(batch_embeddings);
     }
+    Ok(chunks.into_iter().zip(embeddings).collect())
+}
 
-    // Final count
-    let count = vs.count()?;
-    let _ = status_tx.send(format!("Knowledge base built: {} commands indexed.", count));
+/// Stable 64-bit FNV-1a hash of everything embedded for a command, as hex. Covers the
+/// documentation, the package description and the chunking, so a change to any of
+/// them re-embeds the command.
+pub(crate) fn content_hash<'a>(texts: impl Iterator<Item = &'a str>) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for text in texts {
+        // The separator keeps ["ab", "c"] and ["a", "bc"] apart
+        for byte in text.bytes().chain(std::iter::once(0)) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x100000001b3);
+        }
+    }
+    format!("{:016x}", hash)
+}
 
-    // mark ready
-    kb_ready.store(true, Ordering::SeqCst);
+/// Text embedded for a chunk: the command, section and package description give
+/// short chunks (a single option, a SYNOPSIS line) enough context to be found
+fn embedding_text(command_name: &str, package_desc: Option<&str>, chunk: &chunker::Chunk) -> String {
+    match package_desc {
+        Some(desc) => format!("{} {}: {}\n{}", command_name, chunk.section, desc, chunk.content),
+        None => format!("{} {}\n{}", command_name, chunk.section, chunk.content),
+    }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_lock_excludes_other_builds() {
+        let dir = crate::test_support::TempDir::new("lock");
+        let db_path = dir.join("commands.db");
+        let lock = BuildLock::try_acquire(&db_path).unwrap().expect("lock should be free");
+        assert!(build_in_progress(&db_path).unwrap());
+        assert!(BuildLock::try_acquire(&db_path).unwrap().is_none());
+        // A running build's shadow is never reported as interrupted
+        std::fs::write(shadow_path(&db_path), b"").unwrap();
+        assert_eq!(interrupted_build(&db_path).unwrap(), None);
+        drop(lock);
+        assert!(!build_in_progress(&db_path).unwrap());
+    }
+
+    #[test]
+    fn test_content_hash_is_stable_and_separates_texts() {
+        let hash = content_hash(["jq NAME", "jq OPTIONS"].into_iter());
+        assert_eq!(hash, content_hash(["jq NAME", "jq OPTIONS"].into_iter()));
+        assert_eq!(hash.len(), 16);
+        assert_ne!(content_hash(["ab", "c"].into_iter()), content_hash(["a", "bc"].into_iter()));
+        assert_eq!(content_hash(std::iter::empty()), "cbf29ce484222325");
+    }
 }
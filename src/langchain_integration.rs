@@ -52,11 +52,11 @@ impl<'a> VectorStoreRetriever<'a> {
     /// Retrieve documents using semantic similarity
     pub async fn retrieve_async(&self, query: &str, top_k: usize) -> Result<Vec<String>> {
         let query_embedding = self.ollama_client.generate_embedding(query).await?;
-        let similar_commands = self.vector_store.search_similar(&query_embedding, top_k)?;
+        let similar_commands = self.vector_store.search_similar(&query_embedding, top_k, f32::MIN)?;
 
         let docs: Vec<String> = similar_commands
             .iter()
-            .map(|cmd| {
+            .map(|(_, cmd)| {
                 format!(
                     "Tool: {}\nDescription: {}\n",
                     cmd.command_name,
@@ -1,6 +1,6 @@
 use std::fs::{rename, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 const MAX_LOG_BYTES: u64 = 128 * 1024 * 1024; // 128 MB
@@ -20,13 +20,13 @@ fn get_info_log_path() -> Option<PathBuf> {
     Some(dir.join("info.log"))
 }
 
-fn rotate_backups(base: &PathBuf, max_backups: usize) {
+fn rotate_backups(base: &Path, max_backups: usize) {
     // base is like /.../error.log or info.log
     // We want to move: base.(max_backups-1) -> base.max_backups, ... base.1 -> base.2, base -> base.1
     // Use best-effort: ignore errors, but attempt to remove existing target if rename fails
     for i in (1..=max_backups).rev() {
         let src = if i == 1 {
-            base.clone()
+            base.to_path_buf()
         } else {
             base.with_extension(format!("log.{}", i - 1))
         };
@@ -39,11 +39,11 @@ fn rotate_backups(base: &PathBuf, max_backups: usize) {
                 let _ = std::fs::remove_file(&dst);
             }
             // Try rename; on failure, attempt copy then remove
-            if let Err(_) = rename(&src, &dst) {
+            if rename(&src, &dst).is_err() {
                 // fallback: try copy and remove
                 if let (Ok(mut r), Ok(mut w)) = (
                     std::fs::File::open(&src),
-                    OpenOptions::new().create(true).write(true).open(&dst),
+                    OpenOptions::new().create(true).write(true).truncate(true).open(&dst),
                 ) {
                     use std::io::copy;
                     let _ = copy(&mut r, &mut w);
@@ -1,5 +1,6 @@
 mod app;
 mod brew;
+mod package_source;
 mod indexer;
 mod ollama;
 mod log;
@@ -9,25 +10,35 @@ mod vector_store;
 mod langchain_integration;
 mod kb_builder;
 mod config;
+mod hnsw;
+mod embedding_matrix;
+mod embedding_codec;
+mod chunker;
+mod cli;
+#[cfg(test)]
+mod test_support;
 
 use anyhow::Result;
 use app::{App, AppState};
 use crossterm::{
-    event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEventKind, KeyModifiers},
+    event::{DisableMouseCapture, EnableMouseCapture, Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use ollama::OllamaClient;
+use config::Config;
+use futures::StreamExt;
+use ollama::{ChatMessage, OllamaClient};
 use rag::RagPipeline;
 use kb_builder::build_kb;
 use std::sync::{Arc, atomic::AtomicBool, atomic::Ordering};
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, oneshot};
+use std::time::Duration;
 use ratatui::{backend::CrosstermBackend, Terminal};
 use std::io;
 use std::path::PathBuf;
 use vector_store::VectorStore;
-use std::env;
 
+/// Actions triggered by key presses that the event loop carries out
 #[derive(Debug, Clone, Copy)]
 enum AppCommand {
     Quit,
@@ -37,24 +48,10 @@ enum AppCommand {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        let cfg = config::load_config()?;
-        let mut ollama = OllamaClient::new(cfg.ollama_model.clone());
-        ollama.set_embed_model(cfg.embedding_model.clone());
-        ollama.set_base_url(cfg.ollama_url.clone());
-        let packages = brew::get_installed_packages()?;
-        let package_names: Vec<String> = packages.iter().map(|p| p.name.clone()).collect();
-        let query = args[1..].join(" ");
-        match ollama.query(&query, &package_names, None).await {
-            Ok(res) => {
-                println!("{}", res);
-            }
-            Err(e) => {
-                eprintln!("Error: {}", e);
-            }
-        }
-        return Ok(());
+    // Any subcommand runs without the TUI
+    let args = cli::parse_args();
+    if let Some(command) = args.command {
+        return cli::run(command).await;
     }
 
     enable_raw_mode()?;
@@ -63,110 +60,70 @@ async fn main() -> Result<()> {
     let backend = CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
-    // Load Homebrew packages
-    let packages = brew::get_installed_packages()?;
-
     // Load config and initialize Ollama client
     let cfg = config::load_config()?;
+
+    // Load installed packages from the configured package managers
+    let packages = package_source::installed_packages(&cfg.package_sources);
     let mut ollama = OllamaClient::new(cfg.ollama_model.clone());
     ollama.set_embed_model(cfg.embedding_model.clone());
     ollama.set_base_url(cfg.ollama_url.clone());
 
-    // Initialize vector store (open DB now)
-    let db_path = get_db_path()?;
-    let vector_store = VectorStore::new(db_path.clone())?;
+    // Initialize vector store (open DB now); it stays open and keeps embeddings resident
+    let db_path = config::get_db_path()?;
+    let vector_store = Arc::new(VectorStore::new(db_path.clone())?);
+    // A build cut short last time (e.g. by quitting) is continued rather than forgotten
+    let interrupted = kb_builder::interrupted_build(&db_path)?;
+    // ...unless another process (e.g. `mac-aid index`) is still building it
+    let building_elsewhere = kb_builder::build_in_progress(&db_path)?;
+    // Vectors from another embedding model would make every similarity meaningless
+    let stale = vector_store.embedding_mismatch(&cfg.embedding_model)?;
 
     // KB readiness flag and status channel
-    let kb_ready = Arc::new(AtomicBool::new(!vector_store.is_empty()?));
-    let rebuilding = Arc::new(AtomicBool::new(false));
-    let reloading = Arc::new(AtomicBool::new(false));
     let (status_tx, mut status_rx) = mpsc::unbounded_channel::<String>();
-
-    // Spawn background KB builder if not ready
-    if !kb_ready.load(Ordering::SeqCst) {
-        let db_path_clone = db_path.clone();
-        let pkgs = packages.clone();
-        let tx = status_tx.clone();
-        let kb_flag = kb_ready.clone();
-        let cfg_for_build = cfg.clone();
-
-        // Use spawn_blocking + a current-thread runtime because build_kb uses non-Send types (rusqlite::Connection)
-        tokio::task::spawn_blocking(move || {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .expect("failed to build current-thread runtime for KB builder");
-
-            rt.block_on(async move {
-                if let Err(e) = build_kb(db_path_clone, pkgs, tx, kb_flag.clone(), cfg_for_build).await {
-                    crate::log::log_error(&format!("Background KB build failed: {}", e));
-                }
-            });
-        });
+    let ctx = TuiContext {
+        kb_ready: Arc::new(AtomicBool::new(!vector_store.is_empty()? && stale.is_none())),
+        rebuilding: Arc::new(AtomicBool::new(false)),
+        reloading: Arc::new(AtomicBool::new(false)),
+        status_tx,
+        cfg,
+        ollama,
+        db_path,
+        packages,
+        store: vector_store,
+    };
+
+    // Spawn background KB builder if not ready or interrupted, and load the embeddings
+    // of whatever KB is already there up front
+    if ctx.kb_ready.load(Ordering::SeqCst) {
+        ctx.spawn_reload();
+    }
+    if building_elsewhere {
+        let _ = ctx.status_tx.send(
+            "A knowledge base build is already in progress in another process; press Ctrl + l when it finishes".to_string(),
+        );
+    } else if let Some(info) = &stale {
+        let _ = ctx.status_tx.send(format!(
+            "Embedding model changed from {} to {}; rebuilding knowledge base...",
+            info.model, ctx.cfg.embedding_model
+        ));
+        ctx.spawn_build();
+    } else if let Some(run) = &interrupted {
+        let _ = ctx.status_tx.send(format!(
+            "Resuming interrupted knowledge base build ({}/{} documents done)...",
+            run.docs_done, run.docs_total
+        ));
+        ctx.spawn_build();
+    } else if !ctx.kb_ready.load(Ordering::SeqCst) {
+        ctx.spawn_build();
     }
 
     // Create app
     let mut app = App::new();
-
-    // Remove synchronous indexing; background builder handles it
-
     app.set_status(None);
     app.clear_input();
 
-    // Run the app loop
-    loop {
-        // Drain status messages from builder (non-blocking) and show in UI
-        while let Ok(msg) = status_rx.try_recv() {
-            app.push_kb_progress(msg.clone());
-            app.set_status(Some(msg));
-            terminal.draw(|f| ui::render(f, &app))?;
-        }
-
-        let cmd = run_app(&mut terminal, &mut app, &ollama, &db_path, kb_ready.clone(), rebuilding.clone(), reloading.clone(), &packages).await?;
-
-        match cmd {
-            AppCommand::Quit => break,
-            AppCommand::Rebuild => {
-                app.set_status(Some("Rebuild started in background".to_string()));
-                kb_ready.store(false, Ordering::SeqCst);
-                rebuilding.store(true, Ordering::SeqCst);
-                let db_path_clone = db_path.clone();
-                let pkgs = packages.clone();
-                let tx = status_tx.clone();
-                let kb_flag = kb_ready.clone();
-                let rebuilding_flag = rebuilding.clone();
-                let cfg_clone = cfg.clone();
-                tokio::task::spawn_blocking(move || {
-                    let rt = tokio::runtime::Builder::new_current_thread()
-                        .enable_all()
-                        .build()
-                        .expect("failed to build current-thread runtime for KB rebuild");
-                    rt.block_on(async move {
-                        let _ = tx.send("Rebuilding knowledge base...".to_string());
-                        if let Err(e) = build_kb(db_path_clone, pkgs, tx.clone(), kb_flag.clone(), cfg_clone).await {
-                            crate::log::log_error(&format!("Background rebuild failed: {}", e));
-                            let _ = tx.send(format!("Error rebuilding: {}", e));
-                        }
-                        rebuilding_flag.store(false, Ordering::SeqCst);
-                    });
-                });
-                app.clear_input();
-            }
-            AppCommand::Reload => {
-                app.set_status(Some("Reloading index data in background...".to_string()));
-                reloading.store(true, Ordering::SeqCst);
-                let tx = status_tx.clone();
-                let reloading_flag = reloading.clone();
-                tokio::task::spawn(async move {
-                    let _ = tx.send("Reloading index data...".to_string());
-                    // Simple simulate reopen; queries open fresh connections anyway
-                    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
-                    let _ = tx.send("Index reloaded.".to_string());
-                    reloading_flag.store(false, Ordering::SeqCst);
-                });
-            }
-        }
-    }
+    let result = run_app(&mut terminal, &mut app, &ctx, &mut status_rx).await;
 
     // Restore terminal
     disable_raw_mode()?;
@@ -177,184 +134,284 @@ async fn main() -> Result<()> {
     )?;
     terminal.show_cursor()?;
 
-    Ok(())
+    result
 }
 
-async fn run_app<'a>(
-    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-    app: &mut App,
-    ollama: &OllamaClient,
-    db_path: &PathBuf,
+/// Long-lived state shared by the TUI event loop and the background tasks it starts
+struct TuiContext {
+    cfg: Config,
+    ollama: OllamaClient,
+    db_path: PathBuf,
+    packages: Vec<package_source::Package>,
+    // Shared by all queries; reloaded after the KB changes
+    store: Arc<VectorStore>,
     kb_ready: Arc<AtomicBool>,
     rebuilding: Arc<AtomicBool>,
     reloading: Arc<AtomicBool>,
-    packages: &[brew::BrewPackage],
-) -> Result<AppCommand> {
-    loop {
-        terminal.draw(|f| ui::render(f, app))?;
+    status_tx: mpsc::UnboundedSender<String>,
+}
 
-        if let Event::Key(key) = event::read()? {
-            if key.kind != KeyEventKind::Press {
-                continue;
-            }
+impl TuiContext {
+    /// Build the KB in the background, reporting progress on the status channel
+    fn spawn_build(&self) {
+        self.rebuilding.store(true, Ordering::SeqCst);
+        let db_path = self.db_path.clone();
+        let tx = self.status_tx.clone();
+        let kb_flag = self.kb_ready.clone();
+        let rebuilding_flag = self.rebuilding.clone();
+        let cfg = self.cfg.clone();
+        let store = self.store.clone();
+
+        // build_kb's future isn't Send: its extraction and embedding streams map borrowed
+        // items to async blocks, which rustc can't prove Send for every lifetime. Run it on
+        // its own current-thread runtime on a blocking thread, which also keeps the
+        // subprocess-heavy package scan off the async workers.
+        tokio::task::spawn_blocking(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .expect("failed to build current-thread runtime for KB builder");
 
-            match key.code {
-                KeyCode::Char('q') => {
-                    return Ok(AppCommand::Quit);
-                }
-                KeyCode::Char('r') | KeyCode::Char('R') if key.modifiers.contains(KeyModifiers::SHIFT) => {
-                    // Ctrl+Shift+R => reload index data
-                    return Ok(AppCommand::Reload);
-                }
-                KeyCode::Char('r') | KeyCode::Char('R') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    // plain 'r' or 'R' triggers rebuild
-                    return Ok(AppCommand::Rebuild);
-                }
-                KeyCode::Up => {
-                    app.scroll_up();
+            rt.block_on(async move {
+                // Read the inventory again so a rebuild picks up installs and upgrades
+                let pkgs = package_source::installed_packages(&cfg.package_sources);
+                if let Err(e) = build_kb(db_path, pkgs, tx.clone(), kb_flag, cfg, false).await {
+                    crate::log::log_error(&format!("Background KB build failed: {}", e));
+                    let _ = tx.send(format!("Error building knowledge base: {}", e));
                 }
-                KeyCode::Down => {
-                    app.scroll_down();
+                // Pick up whatever the builder wrote through its own connection
+                if let Err(e) = store.reload() {
+                    crate::log::log_error(&format!("Failed to reload vector store after build: {}", e));
                 }
-                KeyCode::Char(c) if matches!(app.state, AppState::Input) => {
-                    app.push_char(c);
+                rebuilding_flag.store(false, Ordering::SeqCst);
+            });
+        });
+    }
+
+    /// Reload the resident embeddings and search index from disk in the background
+    fn spawn_reload(&self) {
+        self.reloading.store(true, Ordering::SeqCst);
+        let tx = self.status_tx.clone();
+        let reloading_flag = self.reloading.clone();
+        let store = self.store.clone();
+        tokio::task::spawn_blocking(move || {
+            let _ = tx.send("Reloading index data...".to_string());
+            match store.reload().and_then(|_| store.command_count()) {
+                Ok(count) => {
+                    let _ = tx.send(format!("Index reloaded: {} commands.", count));
                 }
-                KeyCode::Backspace if matches!(app.state, AppState::Input) => {
-                    app.pop_char();
+                Err(e) => {
+                    crate::log::log_error(&format!("Failed to reload index data: {}", e));
+                    let _ = tx.send(format!("Error reloading index: {}", e));
                 }
-                KeyCode::Enter if matches!(app.state, AppState::Input) => {
-                    let query = app.input.clone();
-                    if !query.is_empty() {
-                        app.set_loading();
-                        terminal.draw(|f| ui::render(f, app))?;
-
-                        let package_names: Vec<String> = packages.iter().map(|p| p.name.clone()).collect();
-
-                        if kb_ready.load(Ordering::SeqCst) && !rebuilding.load(Ordering::SeqCst) && !reloading.load(Ordering::SeqCst) {
-                            // KB ready: open vector store once and reuse for this query
-                            match VectorStore::new(db_path.clone()) {
-                                Ok(vs) => {
-                                    let rag = RagPipeline::new(&vs, ollama);
-                                    match rag.query_with_rag(&query, &package_names, 2).await {
-                                        Ok(response) => {
-                                            app.set_response(response);
-                                            app.clear_input();
-                                        }
-                                        Err(e) => {
-                                            app.set_response(format!("Error: {}", e));
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    crate::log::log_error(&format!("Failed to open vector store for query: {}", e));
-                                    match ollama.query(&query, &package_names, None).await {
-                                        Ok(response) => {
-                                            app.set_response(response);
-                                            app.clear_input();
-                                        }
-                                        Err(e) => {
-                                            app.set_response(format!("Error: {}", e));
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
-                            // KB not ready or busy: directly query local Ollama without RAG
-                            match ollama.query(&query, &package_names, None).await {
-                                Ok(response) => {
-                                    app.set_response(response);
-                                    app.clear_input();
-                                }
-                                Err(e) => {
-                                    app.set_response(format!("Error: {}", e));
-                                }
-                            }
-                        }
+            }
+            reloading_flag.store(false, Ordering::SeqCst);
+        });
+    }
 
-                        // Return to input mode for next query
-                        app.state = AppState::Input;
+    /// RAG is only used once a complete KB is loaded. Rebuilds write to a shadow copy
+    /// that is swapped in when done, so the live KB stays usable while they run.
+    fn kb_usable(&self) -> bool {
+        self.kb_ready.load(Ordering::SeqCst) && !self.reloading.load(Ordering::SeqCst)
+    }
+}
+
+/// Single event loop: terminal input, KB builder status, query output and a redraw
+/// tick are all multiplexed here so every subsystem updates the UI as it happens.
+async fn run_app(
+    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
+    app: &mut App,
+    ctx: &TuiContext,
+    status_rx: &mut mpsc::UnboundedReceiver<String>,
+) -> Result<()> {
+    let mut events = EventStream::new();
+    let mut tick = tokio::time::interval(Duration::from_millis(250));
+    let mut query_task: Option<QueryTask> = None;
+
+    loop {
+        terminal.draw(|f| ui::render(f, app))?;
+
+        tokio::select! {
+            maybe_event = events.next() => match maybe_event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    match handle_key(app, key, ctx, &mut query_task) {
+                        Some(AppCommand::Quit) => return Ok(()),
+                        Some(AppCommand::Rebuild) if ctx.rebuilding.load(Ordering::SeqCst) => {
+                            app.set_status(Some("A knowledge base build is already running".to_string()));
+                        }
+                        Some(AppCommand::Rebuild) => {
+                            // The current KB stays in use until the rebuilt one is swapped in
+                            app.set_status(Some("Rebuild started in background".to_string()));
+                            let _ = ctx.status_tx.send("Rebuilding knowledge base...".to_string());
+                            ctx.spawn_build();
+                            app.clear_input();
+                        }
+                        Some(AppCommand::Reload) => {
+                            app.set_status(Some("Reloading index data in background...".to_string()));
+                            ctx.spawn_reload();
+                        }
+                        None => {}
                     }
                 }
-                KeyCode::Esc if matches!(app.state, AppState::Input) => {
-                    if !app.input.is_empty() {
-                        app.clear_input();
+                // Resize and other events just trigger the redraw at the top of the loop
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+            Some(msg) = status_rx.recv() => {
+                app.push_kb_progress(msg.clone());
+                app.set_status(Some(msg));
+            }
+            event = next_query_event(&mut query_task) => match event {
+                QueryEvent::Chunk(chunk) => app.append_response(&chunk),
+                QueryEvent::Done(result) => {
+                    query_task = None;
+                    match result {
+                        Ok(_) => {
+                            app.finish_turn();
+                            app.clear_input();
+                        }
+                        Err(e) => app.set_response(format!("Error: {}", e)),
                     }
+                    // Return to input mode for next query
+                    app.state = AppState::Input;
                 }
-                _ => {}
-            }
+            },
+            _ = tick.tick() => app.tick(),
         }
 
         if app.should_quit {
-            return Ok(AppCommand::Quit);
+            return Ok(());
+        }
+    }
+}
+
+/// Apply a key press to the app; returns a command for actions handled by the event loop
+fn handle_key(app: &mut App, key: KeyEvent, ctx: &TuiContext, query_task: &mut Option<QueryTask>) -> Option<AppCommand> {
+    match key.code {
+        // Commands need Ctrl so that every plain key can be typed into a question
+        KeyCode::Char('q') | KeyCode::Char('Q') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            return Some(AppCommand::Quit);
+        }
+        KeyCode::Char('l') | KeyCode::Char('L') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            // Ctrl+l => reload index data
+            return Some(AppCommand::Reload);
+        }
+        KeyCode::Char('r') | KeyCode::Char('R') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            // Ctrl+r => update the knowledge base
+            return Some(AppCommand::Rebuild);
+        }
+        KeyCode::Char('n') | KeyCode::Char('N') if key.modifiers.contains(KeyModifiers::CONTROL) && matches!(app.state, AppState::Input) => {
+            app.new_conversation();
+            app.set_status(Some("Started a new conversation".to_string()));
+        }
+        KeyCode::Up => {
+            app.scroll_up();
         }
+        KeyCode::Down => {
+            app.scroll_down();
+        }
+        KeyCode::Char(c) if matches!(app.state, AppState::Input) && !key.modifiers.contains(KeyModifiers::CONTROL) => {
+            app.push_char(c);
+        }
+        KeyCode::Backspace if matches!(app.state, AppState::Input) => {
+            app.pop_char();
+        }
+        KeyCode::Enter if matches!(app.state, AppState::Input) && !app.input.is_empty() => {
+            let query = app.input.clone();
+            let history = app.conversation.clone();
+            app.set_loading();
+            app.start_turn(query.clone());
+
+            let package_names = package_source::prompt_labels(&ctx.packages);
+            let store = ctx.kb_usable().then(|| ctx.store.clone());
+            *query_task = Some(spawn_query(
+                ctx.ollama.clone(),
+                store,
+                ctx.cfg.min_similarity as f32,
+                history,
+                query,
+                package_names,
+            ));
+        }
+        KeyCode::Esc if matches!(app.state, AppState::Loading) => {
+            // Dropping the task aborts the in-flight request; the input is left intact
+            *query_task = None;
+            app.cancel_turn();
+            app.state = AppState::Input;
+            app.set_status(Some("Cancelled".to_string()));
+        }
+        KeyCode::Esc if matches!(app.state, AppState::Input) => {
+            app.clear_input();
+        }
+        _ => {}
     }
+    None
 }
 
-fn get_db_path() -> Result<PathBuf> {
-    let home = dirs::home_dir().ok_or_else(|| anyhow::anyhow!("Could not find home directory"))?;
-    let app_dir = home.join(".mac-aid");
-    std::fs::create_dir_all(&app_dir)?;
-    Ok(app_dir.join("commands.db"))
+enum QueryEvent {
+    Chunk(String),
+    Done(Result<String>),
 }
 
-#[allow(dead_code)]
-async fn rebuild_knowledge_base(
-    vector_store: &mut VectorStore,
-    ollama: &OllamaClient,
-    packages: &[brew::BrewPackage],
-    terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-    app: &mut App,
-) -> Result<()> {
-    // Clear existing entries before rebuilding
-    vector_store.clear()?;
-    app.set_status(Some("Rebuilding knowledge base...".to_string()));
-    terminal.draw(|f| ui::render(f, app))?;
-
-    // Index packages
-    let package_names: Vec<String> = packages.iter().map(|p| p.name.clone()).collect();
-    let docs = indexer::index_brew_packages(&package_names).await?;
-    let total = docs.len();
-
-    // Process docs in batches of 10 for better efficiency
-    let batch_size = 5;
-    for batch_start in (0..total).step_by(batch_size) {
-        let batch_end = std::cmp::min(batch_start + batch_size, total);
-        let batch = &docs[batch_start..batch_end];
-        
-        for doc in batch {
-            match ollama.generate_embedding(&doc.man_content).await {
-                Ok(embedding) => {
-                    if let Err(e) = vector_store.store_command(
-                        &doc.package_name,
-                        &doc.command_name,
-                        &doc.man_content,
-                        &embedding,
-                    ) {
-                        crate::log::log_error(&format!("Failed to store: {}: {}", doc.command_name, e));
-                    }
-                }
-                Err(e) => {
-                    crate::log::log_error(&format!("Failed to embed: {}: {}", doc.command_name, e));
-                }
-            }
-        }
+/// A query answered on a background task. Dropping it aborts the task,
+/// which in turn drops the in-flight HTTP request.
+struct QueryTask {
+    chunk_rx: mpsc::UnboundedReceiver<String>,
+    result_rx: oneshot::Receiver<Result<String>>,
+    handle: tokio::task::JoinHandle<()>,
+}
 
-        // Update status
-        app.set_status(Some(format!("Rebuilding: {}/{} commands", batch_end, total)));
-        terminal.draw(|f| ui::render(f, app))?;
-        
-        // Yield CPU to prevent UI blocking during KB rebuild
-        // 50ms is optimal: long enough to batch process, short enough for responsive UI
-        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+impl Drop for QueryTask {
+    fn drop(&mut self) {
+        self.handle.abort();
     }
+}
 
-    let count = vector_store.count()?;
-    app.set_status(Some(format!("Knowledge base rebuilt! {} commands indexed.", count)));
-    terminal.draw(|f| ui::render(f, app))?;
+impl QueryTask {
+    async fn next_event(&mut self) -> QueryEvent {
+        tokio::select! {
+            // Chunks are always sent before the result, so drain them first
+            biased;
+            Some(chunk) = self.chunk_rx.recv() => QueryEvent::Chunk(chunk),
+            result = &mut self.result_rx => QueryEvent::Done(
+                result.unwrap_or_else(|_| Err(anyhow::anyhow!("Query task ended unexpectedly"))),
+            ),
+        }
+    }
+}
 
-    // Small delay so user sees completion message
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
-    app.set_status(None);
+/// Next event from the running query, or never if nothing is running
+async fn next_query_event(task: &mut Option<QueryTask>) -> QueryEvent {
+    match task {
+        Some(task) => task.next_event().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Start answering a query in the background. RAG is used when a store is given.
+fn spawn_query(
+    ollama: OllamaClient,
+    store: Option<Arc<VectorStore>>,
+    min_similarity: f32,
+    history: Vec<ChatMessage>,
+    query: String,
+    package_names: Vec<String>,
+) -> QueryTask {
+    let (chunk_tx, chunk_rx) = mpsc::unbounded_channel::<String>();
+    let (result_tx, result_rx) = oneshot::channel();
+
+    let handle = tokio::spawn(async move {
+        let res = match &store {
+            Some(vs) => {
+                let rag = RagPipeline::new(vs, &ollama).with_min_similarity(min_similarity);
+                rag.chat_with_rag_stream(&history, &query, &package_names, 2, &chunk_tx).await
+            }
+            // KB not ready or busy: directly query local Ollama without RAG
+            None => ollama.chat_stream(&history, &query, &package_names, None, &chunk_tx).await,
+        };
+        let _ = result_tx.send(res);
+    });
 
-    Ok(())
+    QueryTask { chunk_rx, result_rx, handle }
 }
@@ -1,11 +1,18 @@
 use anyhow::Result;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::sync::Semaphore;
 use tokio::time::{sleep, Duration};
 use rand::Rng;
 
+/// Texts longer than this (in chars) are embedded in pieces
+const MAX_EMBED_CHARS: usize = 2000;
+/// Embedding requests allowed in flight at once unless configured otherwise
+pub const DEFAULT_EMBED_CONCURRENCY: usize = 4;
+
 #[derive(Debug, Serialize, Clone)]
 pub struct OllamaOptions {
     num_ctx: Option<u32>,
@@ -45,20 +52,76 @@ struct OllamaResponse {
     done: bool,
 }
 
+/// Maximum number of prior messages (user + assistant) sent with each chat turn
+const MAX_HISTORY_MESSAGES: usize = 12;
+
+/// Body of `/api/embed`, which takes a batch of inputs
+#[derive(Debug, Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+}
+
+/// A single message in an `/api/chat` conversation
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self { role: "system".to_string(), content: content.into() }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self { role: "user".to_string(), content: content.into() }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self { role: "assistant".to_string(), content: content.into() }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaChatRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+}
+
+/// One line of the NDJSON stream returned by `/api/generate` or `/api/chat` with `stream: true`.
+/// Generate streams carry text in `response`, chat streams in `message.content`.
+#[derive(Debug, Deserialize)]
+struct OllamaStreamChunk {
+    #[serde(default)]
+    response: String,
+    #[serde(default)]
+    message: Option<ChatMessage>,
+    #[serde(default)]
+    done: bool,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+#[derive(Clone)]
 pub struct OllamaClient {
     client: Client,
     base_url: String,
     model: String,
     embed_model: String,
     options: Option<OllamaOptions>,
-    // Limit concurrent embedding requests
+    // Limit concurrent embedding requests (DEFAULT_EMBED_CONCURRENCY unless configured)
     limiter: Arc<Semaphore>,
+    // Set once the server turns out not to have /api/embed (shared by clones)
+    legacy_embed: Arc<AtomicBool>,
     // Retry configuration
     max_retries: usize,
     base_backoff_ms: u64,
-    // Delay between single embedding requests (ms) - used in fallback scenarios
-    #[allow(dead_code)]
-    single_request_delay_ms: u64,
 }
 
 impl OllamaClient {
@@ -69,10 +132,10 @@ impl OllamaClient {
             model,
             embed_model: "all-minilm".to_string(),
             options: None,
-            limiter: Arc::new(Semaphore::new(1)),
+            limiter: Arc::new(Semaphore::new(DEFAULT_EMBED_CONCURRENCY)),
+            legacy_embed: Arc::new(AtomicBool::new(false)),
             max_retries: 8,
             base_backoff_ms: 1500,
-            single_request_delay_ms: 500,
         }
     }
 
@@ -83,6 +146,7 @@ impl OllamaClient {
 
     pub fn set_base_url(&mut self, base_url: String) {
         self.base_url = base_url;
+        self.legacy_embed = Arc::new(AtomicBool::new(false));
     }
 
     fn effective_options(&self) -> OllamaOptions {
@@ -122,115 +186,211 @@ impl OllamaClient {
         Ok(ollama_response.response)
     }
 
+    /// Multi-turn, streaming variant of `query` using `/api/chat`: each generated chunk
+    /// is sent on `chunk_tx` as it arrives and the full answer is returned at the end.
+    /// `history` holds the previous user/assistant turns; `context` is attached to the
+    /// new user turn only.
+    pub async fn chat_stream(
+        &self,
+        history: &[ChatMessage],
+        user_query: &str,
+        packages: &[String],
+        context: Option<&str>,
+        chunk_tx: &UnboundedSender<String>,
+    ) -> Result<String> {
+        let request = self.build_chat_request(self.build_chat_messages(history, user_query, packages, context));
+
+        let response = self
+            .client
+            .post(format!("{}/api/chat", self.base_url))
+            .json(&request)
+            .send()
+            .await?;
+
+        read_stream(response, chunk_tx).await
+    }
+
+    fn build_chat_request(&self, messages: Vec<ChatMessage>) -> OllamaChatRequest {
+        OllamaChatRequest {
+            model: self.model.clone(),
+            messages,
+            stream: true,
+            options: Some(self.effective_options()),
+        }
+    }
+
+    fn build_chat_messages(
+        &self,
+        history: &[ChatMessage],
+        user_query: &str,
+        packages: &[String],
+        context: Option<&str>,
+    ) -> Vec<ChatMessage> {
+        let system = format!(
+            "{}\n\nFor each request, {}\n\nFollow-up questions refer to the earlier conversation. {}",
+            instructions_preamble(packages),
+            RECOMMENDATION_INSTRUCTIONS,
+            FORMAT_INSTRUCTIONS
+        );
+
+        let user = match context {
+            Some(ctx) => format!("Relevant documentation from installed tools:\n{}\n\n{}", ctx, user_query),
+            None => user_query.to_string(),
+        };
+
+        // Only the most recent turns are sent, to keep the prompt within num_ctx
+        let history = &history[history.len().saturating_sub(MAX_HISTORY_MESSAGES)..];
+
+        let mut messages = Vec::with_capacity(history.len() + 2);
+        messages.push(ChatMessage::system(system));
+        messages.extend_from_slice(history);
+        messages.push(ChatMessage::user(user));
+        messages
+    }
+
+    /// Names of the models available on the Ollama server (`/api/tags`)
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        #[derive(Deserialize)]
+        struct TagsResponse {
+            models: Vec<ModelTag>,
+        }
+
+        #[derive(Deserialize)]
+        struct ModelTag {
+            name: String,
+        }
+
+        let response = self
+            .client
+            .get(format!("{}/api/tags", self.base_url))
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            anyhow::bail!("Ollama API request failed: {}", response.status());
+        }
+
+        let tags: TagsResponse = response.json().await?;
+        Ok(tags.models.into_iter().map(|m| m.name).collect())
+    }
+
     /// Generate embeddings for text using Ollama
     pub async fn generate_embedding(&self, text: &str) -> Result<Vec<f32>> {
+        self.generate_embeddings(&[text.to_string()])
+            .await?
+            .pop()
+            .ok_or_else(|| anyhow::anyhow!("Empty embedding"))
+    }
+
+    /// Embed several texts with a single `/api/embed` request. Texts longer than
+    /// MAX_EMBED_CHARS are sent in pieces whose embeddings are averaged by length.
+    pub async fn generate_embeddings(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut owners = Vec::new();
+        let mut pieces = Vec::new();
+        for (i, text) in texts.iter().enumerate() {
+            for piece in split_chars(text, MAX_EMBED_CHARS) {
+                owners.push((i, piece.chars().count().max(1) as f32));
+                pieces.push(piece);
+            }
+        }
+        let embeddings = self.embed_pieces(&pieces).await?;
+        combine_pieces(&owners, embeddings, texts.len())
+    }
+
+    /// One embedding per input, from `/api/embed`, or one request per input to the
+    /// older `/api/embeddings` on Ollama versions that don't have it. The fallback is
+    /// remembered, so later batches go straight to `/api/embeddings`.
+    async fn embed_pieces(&self, pieces: &[String]) -> Result<Vec<Vec<f32>>> {
+        #[derive(Deserialize)]
+        struct EmbedResponse {
+            embeddings: Vec<Vec<f32>>,
+        }
+
         #[derive(Serialize)]
-        struct EmbedRequest {
-            model: String,
-            prompt: String,
+        struct LegacyEmbedRequest<'a> {
+            model: &'a str,
+            prompt: &'a str,
             #[serde(skip_serializing_if = "Option::is_none")]
             options: Option<OllamaOptions>,
         }
 
         #[derive(Deserialize)]
-        struct EmbedResponse {
+        struct LegacyEmbedResponse {
             embedding: Vec<f32>,
         }
 
+        if !self.legacy_embed.load(Ordering::Relaxed) {
+            let request = self.build_embed_request(pieces);
+            let response = self.post_with_retry("/api/embed", &request).await?;
+            if response.status() != reqwest::StatusCode::NOT_FOUND {
+                let embed_response: EmbedResponse = response.json().await?;
+                return Ok(embed_response.embeddings);
+            }
+            // A missing model is also a 404, but with Ollama's JSON error ("model ... not
+            // found, try pulling it first"); only a bare 404 means there's no such route
+            if let Some(error) = ollama_error(&response.text().await.unwrap_or_default()) {
+                anyhow::bail!("Ollama embedding request failed: {}", error);
+            }
+            self.legacy_embed.store(true, Ordering::Relaxed);
+        }
+
         let options = Some(self.effective_options());
-        let model = self.embed_model.clone();
-        let chars: Vec<char> = text.chars().collect();
-        let max_chunk = 2000usize;
-        if chars.len() <= max_chunk {
-            let request = EmbedRequest { model: model.clone(), prompt: text.to_string(), options: options.clone() };
-            for attempt in 0..self.max_retries {
-                let permit = self.limiter.clone().acquire_owned().await.unwrap();
-                let resp_result = self.client.post(format!("{}/api/embeddings", self.base_url)).json(&request).send().await;
-                drop(permit);
-                match resp_result {
-                    Ok(response) => {
-                        if response.status().is_success() {
-                            let embed_response: EmbedResponse = response.json().await?;
-                            return Ok(embed_response.embedding);
-                        } else {
-                            let status = response.status();
-                            if status.as_u16() == 429 || status.is_server_error() {
-                                if attempt + 1 == self.max_retries { anyhow::bail!("Ollama embedding request failed after retries: {}", status); }
-                            } else {
-                                anyhow::bail!("Ollama embedding request failed: {}", status);
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        if attempt + 1 == self.max_retries { return Err(anyhow::anyhow!(e)); }
-                    }
-                }
-                let exp = 2u64.pow(attempt as u32);
-                let base = self.base_backoff_ms.saturating_mul(exp);
-                let mut rng = rand::thread_rng();
-                let jitter: u64 = rng.gen_range(0..=base);
-                let backoff = Duration::from_millis(base.saturating_add(jitter));
-                sleep(backoff).await;
+        let mut embeddings = Vec::with_capacity(pieces.len());
+        for piece in pieces {
+            let request = LegacyEmbedRequest { model: &self.embed_model, prompt: piece, options: options.clone() };
+            let response = self.post_with_retry("/api/embeddings", &request).await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                let error = ollama_error(&body).unwrap_or_else(|| status.to_string());
+                anyhow::bail!("Ollama embedding request failed: {}", error);
             }
-            anyhow::bail!("Failed to get embedding after retries")
+            let embed_response: LegacyEmbedResponse = response.json().await?;
+            embeddings.push(embed_response.embedding);
         }
+        Ok(embeddings)
+    }
+
+    fn build_embed_request<'a>(&'a self, input: &'a [String]) -> EmbedRequest<'a> {
+        EmbedRequest { model: &self.embed_model, input, options: Some(self.effective_options()) }
+    }
 
-        let mut sum: Option<Vec<f32>> = None;
-        let mut total: f32 = 0.0;
-        let mut start = 0usize;
-        while start < chars.len() {
-            let end = std::cmp::min(start + max_chunk, chars.len());
-            let chunk: String = chars[start..end].iter().collect();
-            let weight = (end - start) as f32;
-            let request = EmbedRequest { model: model.clone(), prompt: chunk, options: options.clone() };
-            let embedding = {
-                let mut result: Option<Vec<f32>> = None;
-                for attempt in 0..self.max_retries {
-                    let permit = self.limiter.clone().acquire_owned().await.unwrap();
-                    let resp_result = self.client.post(format!("{}/api/embeddings", self.base_url)).json(&request).send().await;
-                    drop(permit);
-                    match resp_result {
-                        Ok(response) => {
-                            if response.status().is_success() {
-                                let embed_response: EmbedResponse = response.json().await?;
-                                result = Some(embed_response.embedding);
-                                break;
-                            } else {
-                                let status = response.status();
-                                if status.as_u16() == 429 || status.is_server_error() {
-                                    if attempt + 1 == self.max_retries { anyhow::bail!("Ollama embedding request failed after retries: {}", status); }
-                                } else {
-                                    anyhow::bail!("Ollama embedding request failed: {}", status);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            if attempt + 1 == self.max_retries { return Err(anyhow::anyhow!(e)); }
-                        }
+    /// POST an embedding request, holding a slot of the concurrency limiter while it
+    /// runs. Rate limiting (429), server errors and connection failures are retried with
+    /// exponential backoff; a 404 is returned to the caller, other failures are errors.
+    async fn post_with_retry<T: Serialize>(&self, path: &str, body: &T) -> Result<reqwest::Response> {
+        for attempt in 0..self.max_retries {
+            let permit = self.limiter.clone().acquire_owned().await?;
+            let resp_result = self.client.post(format!("{}{}", self.base_url, path)).json(body).send().await;
+            drop(permit);
+            match resp_result {
+                Ok(response) => {
+                    let status = response.status();
+                    if status.is_success() || status == reqwest::StatusCode::NOT_FOUND {
+                        return Ok(response);
+                    }
+                    if status.as_u16() == 429 || status.is_server_error() {
+                        if attempt + 1 == self.max_retries { anyhow::bail!("Ollama embedding request failed after retries: {}", status); }
+                    } else {
+                        anyhow::bail!("Ollama embedding request failed: {}", status);
                     }
-                    let exp = 2u64.pow(attempt as u32);
-                    let base = self.base_backoff_ms.saturating_mul(exp);
-                    let mut rng = rand::thread_rng();
-                    let jitter: u64 = rng.gen_range(0..=base);
-                    let backoff = Duration::from_millis(base.saturating_add(jitter));
-                    sleep(backoff).await;
                 }
-                result.ok_or_else(|| anyhow::anyhow!("Failed to get embedding after retries"))?
-            };
-            if let Some(acc) = &mut sum {
-                if acc.len() != embedding.len() { anyhow::bail!("Embedding dimension mismatch"); }
-                for i in 0..acc.len() { acc[i] += embedding[i] * weight; }
-            } else {
-                let mut v = embedding;
-                for i in 0..v.len() { v[i] *= weight; }
-                sum = Some(v);
+                Err(e) => {
+                    if attempt + 1 == self.max_retries { return Err(anyhow::anyhow!(e)); }
+                }
             }
-            total += weight;
-            start = end;
+            let exp = 2u64.pow(attempt as u32);
+            let base = self.base_backoff_ms.saturating_mul(exp);
+            let jitter: u64 = rand::thread_rng().gen_range(0..=base);
+            let backoff = Duration::from_millis(base.saturating_add(jitter));
+            sleep(backoff).await;
         }
-        let mut out = sum.ok_or_else(|| anyhow::anyhow!("Empty embedding"))?;
-        for i in 0..out.len() { out[i] /= total; }
-        Ok(out)
+        anyhow::bail!("Failed to get embedding after retries")
+    }
+
+    /// Allow this many embedding requests in flight at once (shared by clones of this client)
+    pub fn set_embed_concurrency(&mut self, concurrency: usize) {
+        self.limiter = Arc::new(Semaphore::new(concurrency.max(1)));
     }
 
     #[allow(dead_code)]
@@ -238,23 +398,13 @@ impl OllamaClient {
         self.embed_model = embed_model;
     }
 
+    /// Model used for embeddings
+    pub fn embed_model(&self) -> &str {
+        &self.embed_model
+    }
 
-    fn build_prompt(&self, user_query: &str, packages: &[String], context: Option<&str>) -> String {
-        fn format_packages(packages: &[String]) -> (String, usize, bool) {
-            let max_items = 80usize;
-            let max_chars = 2000usize;
-            let mut buf = String::new();
-            let mut count = 0usize;
-            for p in packages.iter().take(max_items) {
-                let add = if buf.is_empty() { p.clone() } else { format!(", {}", p) };
-                if buf.len() + add.len() > max_chars { break; }
-                buf.push_str(&add);
-                count += 1;
-            }
-            let truncated = count < packages.len();
-            (buf, count, truncated)
-        }
 
+    fn build_prompt(&self, user_query: &str, packages: &[String], context: Option<&str>) -> String {
         let context_section = if let Some(ctx) = context {
             format!(
                 r#"
@@ -269,32 +419,142 @@ Relevant documentation from installed tools:
             String::new()
         };
 
-        let (pkg_str, shown, truncated) = format_packages(packages);
-        let pkg_header = if truncated {
-            format!("{}\n(Showing {} of {} installed tools)", pkg_str, shown, packages.len())
-        } else {
-            pkg_str
-        };
-
         format!(
-            r#"You are a helpful assistant that recommends command-line tools based on user needs.
-
-Available tools installed via Homebrew:
-{}{}
-User query: {}
+            "{}{}\nUser query: {}\n\nPlease {}\n\n{}",
+            instructions_preamble(packages),
+            context_section,
+            user_query,
+            RECOMMENDATION_INSTRUCTIONS,
+            FORMAT_INSTRUCTIONS
+        )
+    }
+}
 
-Please recommend the most suitable tool(s) from the available list and provide:
+/// What the model should include for each recommendation; shared by the chat and
+/// single-prompt paths
+const RECOMMENDATION_INSTRUCTIONS: &str = "recommend the most suitable tool(s) from the available list and provide:
 1. The tool name
 2. A brief description of what it does
 3. A practical usage example with command-line syntax
-4. The specific use case scenario
+4. The specific use case scenario";
 
-Format your response clearly and concisely."#,
-            pkg_header,
-            context_section,
-            user_query
-        )
+const FORMAT_INSTRUCTIONS: &str = "Format your response clearly and concisely.";
+
+/// The assistant's role and the installed tools it may recommend
+fn instructions_preamble(packages: &[String]) -> String {
+    format!(
+        "You are a helpful assistant that recommends command-line tools based on user needs.\n\nAvailable tools installed on this system:\n{}",
+        package_header(packages)
+    )
+}
+
+/// The message of an Ollama error response (`{"error": "..."}`), if `body` is one
+fn ollama_error(body: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    Some(value.get("error")?.as_str()?.to_string())
+}
+
+/// Split text into pieces of at most `max` chars; empty text is one empty piece
+fn split_chars(text: &str, max: usize) -> Vec<String> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() {
+        return vec![String::new()];
+    }
+    chars.chunks(max).map(|piece| piece.iter().collect()).collect()
+}
+
+/// Average the embeddings of each text's pieces, weighted by piece length.
+/// `owners[i]` is (text index, weight) of the i-th embedding.
+fn combine_pieces(owners: &[(usize, f32)], embeddings: Vec<Vec<f32>>, texts: usize) -> Result<Vec<Vec<f32>>> {
+    if embeddings.len() != owners.len() {
+        anyhow::bail!("Ollama returned {} embeddings for {} inputs", embeddings.len(), owners.len());
+    }
+    let mut sums: Vec<Option<Vec<f32>>> = vec![None; texts];
+    let mut totals = vec![0.0f32; texts];
+    for (&(text, weight), embedding) in owners.iter().zip(embeddings) {
+        match &mut sums[text] {
+            Some(acc) => {
+                if acc.len() != embedding.len() { anyhow::bail!("Embedding dimension mismatch"); }
+                for (a, e) in acc.iter_mut().zip(embedding.iter()) { *a += e * weight; }
+            }
+            slot => *slot = Some(embedding.into_iter().map(|x| x * weight).collect()),
+        }
+        totals[text] += weight;
+    }
+    sums.into_iter()
+        .zip(totals)
+        .map(|(sum, total)| {
+            let mut out = sum.ok_or_else(|| anyhow::anyhow!("Empty embedding"))?;
+            for x in out.iter_mut() { *x /= total; }
+            Ok(out)
+        })
+        .collect()
+}
+
+/// Comma-separated package list capped to keep prompts within the context window
+fn package_header(packages: &[String]) -> String {
+    let max_items = 80usize;
+    // Entries carry a short description, so allow more room than bare names need
+    let max_chars = 4000usize;
+    let mut buf = String::new();
+    let mut count = 0usize;
+    for p in packages.iter().take(max_items) {
+        let add = if buf.is_empty() { p.clone() } else { format!(", {}", p) };
+        if buf.len() + add.len() > max_chars { break; }
+        buf.push_str(&add);
+        count += 1;
+    }
+    if count < packages.len() {
+        format!("{}\n(Showing {} of {} installed tools)", buf, count, packages.len())
+    } else {
+        buf
+    }
+}
+
+/// Forward an NDJSON generate/chat stream to `chunk_tx`, returning the full text
+async fn read_stream(mut response: reqwest::Response, chunk_tx: &UnboundedSender<String>) -> Result<String> {
+    if !response.status().is_success() {
+        anyhow::bail!("Ollama API request failed: {}", response.status());
+    }
+
+    // Ollama sends one JSON object per line; network chunks may split or merge lines
+    let mut pending: Vec<u8> = Vec::new();
+    let mut full = String::new();
+    while let Some(bytes) = response.chunk().await? {
+        pending.extend_from_slice(&bytes);
+        while let Some(pos) = pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=pos).collect();
+            if handle_stream_line(&line, &mut full, chunk_tx)? {
+                return Ok(full);
+            }
+        }
+    }
+    handle_stream_line(&pending, &mut full, chunk_tx)?;
+
+    Ok(full)
+}
+
+/// Parse one NDJSON line from a generate or chat stream, forwarding any text to `chunk_tx`.
+/// Returns true once Ollama reports the stream is done.
+fn handle_stream_line(line: &[u8], full: &mut String, chunk_tx: &UnboundedSender<String>) -> Result<bool> {
+    let line = std::str::from_utf8(line)?.trim();
+    if line.is_empty() {
+        return Ok(false);
+    }
+
+    let chunk: OllamaStreamChunk = serde_json::from_str(line)?;
+    if let Some(err) = chunk.error {
+        anyhow::bail!("Ollama stream error: {}", err);
+    }
+    let text = match chunk.message {
+        Some(message) => message.content,
+        None => chunk.response,
+    };
+    if !text.is_empty() {
+        full.push_str(&text);
+        let _ = chunk_tx.send(text);
     }
+    Ok(chunk.done)
 }
 
 #[cfg(test)]
@@ -320,6 +580,73 @@ mod tests {
         assert!(p.contains("CTX"));
     }
 
+    #[test]
+    fn test_ollama_error_distinguishes_missing_model_from_missing_route() {
+        let body = r#"{"error":"model \"nomic-embed-text\" not found, try pulling it first"}"#;
+        assert_eq!(ollama_error(body).unwrap(), "model \"nomic-embed-text\" not found, try pulling it first");
+        assert_eq!(ollama_error("404 page not found"), None);
+        assert_eq!(ollama_error(""), None);
+    }
+
+    /// Serve `/api/embeddings` like an Ollama without `/api/embed`, counting requests per path
+    async fn legacy_server(hits: Arc<std::sync::Mutex<Vec<String>>>) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                // Headers, then as much body as Content-Length says
+                let body_len = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length = text
+                            .lines()
+                            .find_map(|l| l.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                            .and_then(|v| v.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        break end + 4 + length;
+                    }
+                };
+                while request.len() < body_len {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let path = String::from_utf8_lossy(&request).split(' ').nth(1).unwrap_or("").to_string();
+                let (status, body) = if path == "/api/embeddings" {
+                    ("200 OK", r#"{"embedding":[1.0,0.0]}"#)
+                } else {
+                    ("404 Not Found", "404 page not found")
+                };
+                hits.lock().unwrap().push(path);
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_missing_embed_route_is_only_tried_once() {
+        let hits = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut client = OllamaClient::new("model".to_string());
+        client.set_base_url(legacy_server(hits.clone()).await);
+
+        let pieces = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(client.embed_pieces(&pieces).await.unwrap().len(), 2);
+        // A clone made for another batch knows about the fallback too
+        assert_eq!(client.clone().embed_pieces(&pieces[..1]).await.unwrap(), vec![vec![1.0, 0.0]]);
+        assert_eq!(*hits.lock().unwrap(), vec!["/api/embed", "/api/embeddings", "/api/embeddings", "/api/embeddings"]);
+    }
+
     #[test]
     fn test_build_generate_request_includes_options() {
         let mut client = OllamaClient::new("model".to_string());
@@ -354,6 +681,29 @@ mod tests {
         assert!(json.contains("2048"));
     }
 
+    #[test]
+    fn test_embed_request_batches_inputs() {
+        let client = OllamaClient::new("model".to_string());
+        let input = vec!["first".to_string(), "second".to_string()];
+        let json = serde_json::to_value(client.build_embed_request(&input)).unwrap();
+        assert_eq!(json["model"], "all-minilm");
+        assert_eq!(json["input"], serde_json::json!(["first", "second"]));
+        assert_eq!(json["options"]["num_ctx"], 8192);
+    }
+
+    #[test]
+    fn test_long_texts_are_split_and_averaged() {
+        assert_eq!(split_chars("abcde", 2), vec!["ab", "cd", "e"]);
+        assert_eq!(split_chars("", 2), vec![""]);
+
+        // Text 0 is one piece; text 1 is two pieces of weight 3 and 1
+        let owners = [(0, 2.0), (1, 3.0), (1, 1.0)];
+        let embeddings = vec![vec![1.0, 0.0], vec![1.0, 1.0], vec![5.0, 1.0]];
+        let combined = combine_pieces(&owners, embeddings, 2).unwrap();
+        assert_eq!(combined, vec![vec![1.0, 0.0], vec![2.0, 1.0]]);
+        assert!(combine_pieces(&owners, vec![vec![1.0]], 2).is_err());
+    }
+
     #[test]
     fn test_default_num_ctx_is_8192_for_generate() {
         let client = OllamaClient::new("model".to_string());
@@ -370,4 +720,64 @@ mod tests {
         let opts = client.effective_options();
         assert_eq!(opts.num_ctx, Some(1024));
     }
+
+    #[test]
+    fn test_handle_stream_line_forwards_chunks_until_done() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut full = String::new();
+        let done = handle_stream_line(br#"{"response":"Use ","done":false}"#, &mut full, &tx).unwrap();
+        assert!(!done);
+        let done = handle_stream_line(b"{\"response\":\"tar\",\"done\":false}\n", &mut full, &tx).unwrap();
+        assert!(!done);
+        let done = handle_stream_line(br#"{"response":"","done":true}"#, &mut full, &tx).unwrap();
+        assert!(done);
+        assert_eq!(full, "Use tar");
+        assert_eq!(rx.try_recv().unwrap(), "Use ");
+        assert_eq!(rx.try_recv().unwrap(), "tar");
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_handle_stream_line_surfaces_errors() {
+        let (tx, _rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut full = String::new();
+        assert!(!handle_stream_line(b"  \n", &mut full, &tx).unwrap());
+        assert!(handle_stream_line(br#"{"error":"model not found"}"#, &mut full, &tx).is_err());
+    }
+
+    #[test]
+    fn test_handle_stream_line_reads_chat_messages() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut full = String::new();
+        let line = br#"{"message":{"role":"assistant","content":"find . -name"},"done":false}"#;
+        assert!(!handle_stream_line(line, &mut full, &tx).unwrap());
+        assert_eq!(full, "find . -name");
+        assert_eq!(rx.try_recv().unwrap(), "find . -name");
+    }
+
+    #[test]
+    fn test_build_chat_messages_orders_system_history_and_query() {
+        let client = OllamaClient::new("model".to_string());
+        let history = vec![ChatMessage::user("compress a file"), ChatMessage::assistant("use gzip")];
+        let packages = vec!["gzip".to_string()];
+        let msgs = client.build_chat_messages(&history, "now recursively", &packages, Some("CTX"));
+        assert_eq!(msgs.len(), 4);
+        assert_eq!(msgs[0].role, "system");
+        assert!(msgs[0].content.contains("gzip"));
+        assert_eq!(msgs[1], history[0]);
+        assert_eq!(msgs[2], history[1]);
+        assert_eq!(msgs[3].role, "user");
+        assert!(msgs[3].content.contains("CTX"));
+        assert!(msgs[3].content.ends_with("now recursively"));
+    }
+
+    #[test]
+    fn test_build_chat_request_streams_with_options() {
+        let client = OllamaClient::new("model".to_string());
+        let req = client.build_chat_request(vec![ChatMessage::user("hi")]);
+        let json = serde_json::to_string(&req).unwrap();
+        assert!(json.contains("\"stream\":true"));
+        assert!(json.contains("\"messages\":[{\"role\":\"user\",\"content\":\"hi\"}]"));
+        assert!(json.contains("8192"));
+    }
 }
@@ -0,0 +1,823 @@
+//! Package managers the inventory is read from. Homebrew is one source among
+//! several (dpkg/apt, cargo, pipx, npm, nix, plus an opt-in scan of $PATH); which
+//! ones are used is set by `package_sources` in the config, and sources that aren't
+//! installed are skipped.
+
+use anyhow::Result;
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+
+/// Every source, in the order their packages are listed
+pub const ALL_SOURCES: &[&str] = &["brew", "apt", "cargo", "pipx", "npm", "nix", PATH_SOURCE];
+/// Sources used when the config doesn't list any: every package manager, but not the PATH scan
+pub const DEFAULT_SOURCES: &[&str] = &["brew", "apt", "cargo", "pipx", "npm", "nix"];
+/// Name of the PATH scanner, whose packages are PATH directories rather than real packages
+pub const PATH_SOURCE: &str = "path";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageKind {
+    /// Homebrew formula
+    Formula,
+    /// Homebrew cask
+    Cask,
+    /// Any other manager's package
+    Package,
+}
+
+impl PackageKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PackageKind::Formula => "formula",
+            PackageKind::Cask => "cask",
+            PackageKind::Package => "package",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Package {
+    pub name: String,
+    /// Name of the source that reported the package, e.g. "brew" or "cargo"
+    pub source: String,
+    pub kind: PackageKind,
+    /// e.g. "homebrew/core" or "user/repo" for third-party taps
+    pub tap: Option<String>,
+    /// Installed version (the newest, if several are installed side by side)
+    pub version: Option<String>,
+    /// One-line description, e.g. "Lightweight and flexible command-line JSON processor"
+    pub desc: Option<String>,
+    pub homepage: Option<String>,
+    /// False for packages that were only pulled in as a dependency
+    pub installed_on_request: bool,
+    /// Commands the source already knows about (cask binaries, cargo/pipx/npm bins);
+    /// other packages are inspected through their files
+    pub binaries: Vec<String>,
+    /// Install prefix containing bin/ and share/man, when the source reports one (nix)
+    pub location: Option<PathBuf>,
+}
+
+impl Package {
+    /// A package with only the fields every source knows
+    pub fn new(source: &str, name: String) -> Self {
+        Self {
+            name,
+            source: source.to_string(),
+            kind: PackageKind::Package,
+            tap: None,
+            version: None,
+            desc: None,
+            homepage: None,
+            installed_on_request: true,
+            binaries: Vec::new(),
+            location: None,
+        }
+    }
+
+    /// "name (description)" as shown to the model
+    pub fn prompt_label(&self) -> String {
+        match &self.desc {
+            Some(desc) if !desc.is_empty() => format!("{} ({})", self.name, desc),
+            _ => self.name.clone(),
+        }
+    }
+}
+
+/// Labels for the package list in prompts: packages installed on request first, so
+/// dependencies are the ones dropped when the list has to be cut short. PATH
+/// directories aren't tools and are left out; their commands reach the model through retrieval.
+pub fn prompt_labels(packages: &[Package]) -> Vec<String> {
+    let (requested, dependencies): (Vec<&Package>, Vec<&Package>) = packages
+        .iter()
+        .filter(|p| p.source != PATH_SOURCE)
+        .partition(|p| p.installed_on_request);
+    requested.into_iter().chain(dependencies).map(Package::prompt_label).collect()
+}
+
+/// A command installed by a package: an executable, a section 1/8 man page, or both
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackageCommand {
+    pub name: String,
+    pub executable: Option<PathBuf>,
+    pub man_page: Option<PathBuf>,
+    /// Options the command is run with, in order, when it has no man page
+    pub help_options: &'static [&'static str],
+}
+
+/// Help options tried for commands a package manager installed
+pub const HELP_OPTIONS: &[&str] = &["-h", "--help", "-help"];
+/// Only `--help` for arbitrary executables found on PATH: `-h` means something else to
+/// many tools (`shutdown -h` halts)
+const PATH_HELP_OPTIONS: &[&str] = &["--help"];
+
+impl PackageCommand {
+    /// A command known only by name, looked up on PATH and MANPATH
+    pub fn named(name: &str) -> Self {
+        Self { name: name.to_string(), executable: None, man_page: None, help_options: HELP_OPTIONS }
+    }
+}
+
+pub trait PackageSource: Send + Sync {
+    /// Name used in the config and to tag packages
+    fn name(&self) -> &'static str;
+
+    /// Whether the package manager is installed on this machine
+    fn is_available(&self) -> bool;
+
+    fn installed_packages(&self) -> Result<Vec<Package>>;
+
+    /// Commands a package from this source installs
+    fn package_commands(&self, package: &Package) -> Vec<PackageCommand>;
+}
+
+pub fn source_by_name(name: &str) -> Option<Box<dyn PackageSource>> {
+    match name {
+        "brew" => Some(Box::new(crate::brew::BrewSource::default())),
+        "apt" => Some(Box::new(AptSource::default())),
+        "cargo" => Some(Box::new(CargoSource)),
+        "pipx" => Some(Box::new(PipxSource)),
+        "npm" => Some(Box::new(NpmSource)),
+        "nix" => Some(Box::new(NixSource)),
+        PATH_SOURCE => Some(Box::new(PathSource)),
+        _ => None,
+    }
+}
+
+/// The configured sources that exist on this machine. Unknown names are logged.
+pub fn available_sources(names: &[String]) -> Vec<Box<dyn PackageSource>> {
+    let mut sources = Vec::new();
+    for name in names {
+        match source_by_name(name) {
+            Some(source) if source.is_available() => sources.push(source),
+            Some(_) => crate::log::log_info(&format!("Package source {} is not installed; skipping", name)),
+            None => crate::log::log_error(&format!("Unknown package source in config: {}", name)),
+        }
+    }
+    sources
+}
+
+/// Merged inventory of all configured sources. A failing source is logged and
+/// skipped, so this never fails just because e.g. brew is missing.
+pub fn installed_packages(names: &[String]) -> Vec<Package> {
+    let mut packages = Vec::new();
+    for source in available_sources(names) {
+        match source.installed_packages() {
+            Ok(found) => packages.extend(found),
+            Err(e) => crate::log::log_error(&format!("Failed to list {} packages: {}", source.name(), e)),
+        }
+    }
+    packages
+}
+
+/// Whether `program arg` runs successfully
+pub fn tool_available(program: &str, arg: &str) -> bool {
+    Command::new(program)
+        .arg(arg)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false)
+}
+
+pub(crate) fn command_stdout(program: &str, args: &[&str]) -> Result<String> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        anyhow::bail!("Failed to execute {} {} command", program, args.join(" "));
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Every command under an install prefix (bin/, sbin/, share/man/man{1,8}), sorted by name
+pub fn commands_in_prefix(prefix: &Path) -> Vec<PackageCommand> {
+    let executables = ["bin", "sbin"].iter().flat_map(|dir| dir_entries(&prefix.join(dir)));
+    let man_pages = ["man1", "man8"].iter().flat_map(|section| dir_entries(&prefix.join("share/man").join(section)));
+    collect_commands(executables, man_pages)
+}
+
+/// Merge executables and man pages into one command per name, sorted by name.
+/// Non-executable files and man pages without a section suffix are ignored.
+fn collect_commands(
+    executables: impl Iterator<Item = PathBuf>,
+    man_pages: impl Iterator<Item = PathBuf>,
+) -> Vec<PackageCommand> {
+    let mut commands: BTreeMap<String, PackageCommand> = BTreeMap::new();
+
+    for path in executables {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()).map(str::to_string) else {
+            continue;
+        };
+        if is_executable(&path) {
+            commands
+                .entry(name.clone())
+                .or_insert(PackageCommand::named(&name))
+                .executable = Some(path);
+        }
+    }
+
+    for path in man_pages {
+        let Some(name) = path.file_name().and_then(|n| n.to_str()).and_then(man_page_name) else {
+            continue;
+        };
+        let entry = commands.entry(name.clone()).or_insert(PackageCommand::named(&name));
+        entry.man_page.get_or_insert(path);
+    }
+
+    commands.into_values().collect()
+}
+
+/// metadata() follows the symlinks many packages put in bin/
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path)
+        .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+fn dir_entries(dir: &Path) -> Vec<PathBuf> {
+    match std::fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok().map(|e| e.path())).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// "gls.1" / "python3.12.1.gz" / "sshd.8" -> command name
+fn man_page_name(file_name: &str) -> Option<String> {
+    let name = file_name.strip_suffix(".gz").unwrap_or(file_name);
+    let (stem, section) = name.rsplit_once('.')?;
+    (!stem.is_empty() && section.starts_with(|c: char| c.is_ascii_digit())).then(|| stem.to_string())
+}
+
+/// Commands from a list of names, using `bin_dir/<name>` when it exists
+fn commands_in_bin_dir(names: &[String], bin_dir: Option<&Path>) -> Vec<PackageCommand> {
+    names
+        .iter()
+        .map(|name| {
+            let executable = bin_dir.map(|dir| dir.join(name)).filter(|p| p.exists());
+            PackageCommand { executable, ..PackageCommand::named(name) }
+        })
+        .collect()
+}
+
+/// Debian/Ubuntu packages via dpkg, with apt's manual/automatic marks
+#[derive(Default)]
+pub struct AptSource {
+    // dpkg's native architecture, for multi-arch file lists (name:arch.list)
+    arch: OnceLock<Option<String>>,
+}
+
+const DPKG_INFO_DIR: &str = "/var/lib/dpkg/info";
+const SYSTEM_BIN_DIRS: &[&str] = &["/usr/bin/", "/usr/sbin/", "/bin/", "/sbin/", "/usr/games/", "/usr/local/bin/"];
+
+impl PackageSource for AptSource {
+    fn name(&self) -> &'static str {
+        "apt"
+    }
+
+    fn is_available(&self) -> bool {
+        tool_available("dpkg-query", "--version")
+    }
+
+    fn installed_packages(&self) -> Result<Vec<Package>> {
+        let listing = command_stdout(
+            "dpkg-query",
+            &["-W", "-f", "${db:Status-Abbrev}\t${Package}\t${Version}\t${binary:Summary}\n"],
+        )?;
+        // Without apt-mark every package counts as wanted
+        let manual = command_stdout("apt-mark", &["showmanual"]).ok();
+        Ok(parse_dpkg(&listing, manual.as_deref()))
+    }
+
+    fn package_commands(&self, package: &Package) -> Vec<PackageCommand> {
+        let files = self.file_list(&package.name);
+        let paths = || files.lines().map(str::trim).filter(|l| !l.is_empty());
+        let executables = paths()
+            .filter(|p| SYSTEM_BIN_DIRS.iter().any(|dir| p.strip_prefix(dir).is_some_and(|rest| !rest.contains('/'))))
+            .map(PathBuf::from);
+        let man_pages = paths()
+            .filter(|p| p.starts_with("/usr/share/man/man1/") || p.starts_with("/usr/share/man/man8/"))
+            .map(PathBuf::from);
+        collect_commands(executables.collect::<Vec<_>>().into_iter(), man_pages.collect::<Vec<_>>().into_iter())
+    }
+}
+
+impl AptSource {
+    /// Files installed by a package, read from dpkg's database instead of running dpkg -L
+    fn file_list(&self, name: &str) -> String {
+        let plain = Path::new(DPKG_INFO_DIR).join(format!("{}.list", name));
+        if let Ok(list) = std::fs::read_to_string(plain) {
+            return list;
+        }
+        let arch = self.arch.get_or_init(|| {
+            command_stdout("dpkg", &["--print-architecture"]).ok().map(|a| a.trim().to_string())
+        });
+        if let Some(arch) = arch {
+            if let Ok(list) = std::fs::read_to_string(Path::new(DPKG_INFO_DIR).join(format!("{}:{}.list", name, arch))) {
+                return list;
+            }
+        }
+        command_stdout("dpkg-query", &["-L", name]).unwrap_or_default()
+    }
+}
+
+/// `dpkg-query -W` lines of "status\tname\tversion\tsummary"; only installed ("ii") packages are kept
+fn parse_dpkg(listing: &str, manual: Option<&str>) -> Vec<Package> {
+    let manual: Option<HashSet<&str>> = manual.map(|m| m.lines().map(str::trim).collect());
+    listing
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let status = fields.next()?;
+            if !status.starts_with("ii") {
+                return None;
+            }
+            let name = fields.next()?.to_string();
+            let mut package = Package::new("apt", name);
+            package.version = fields.next().filter(|v| !v.is_empty()).map(str::to_string);
+            package.desc = fields.next().filter(|d| !d.is_empty()).map(str::to_string);
+            package.installed_on_request = manual.as_ref().is_none_or(|m| m.contains(package.name.as_str()));
+            Some(package)
+        })
+        .collect()
+}
+
+/// Binaries installed with `cargo install`
+pub struct CargoSource;
+
+fn cargo_bin_dir() -> Option<PathBuf> {
+    match std::env::var_os("CARGO_HOME") {
+        Some(home) => Some(PathBuf::from(home).join("bin")),
+        None => dirs::home_dir().map(|h| h.join(".cargo/bin")),
+    }
+}
+
+impl PackageSource for CargoSource {
+    fn name(&self) -> &'static str {
+        "cargo"
+    }
+
+    fn is_available(&self) -> bool {
+        tool_available("cargo", "--version")
+    }
+
+    fn installed_packages(&self) -> Result<Vec<Package>> {
+        Ok(parse_cargo_install_list(&command_stdout("cargo", &["install", "--list"])?))
+    }
+
+    fn package_commands(&self, package: &Package) -> Vec<PackageCommand> {
+        commands_in_bin_dir(&package.binaries, cargo_bin_dir().as_deref())
+    }
+}
+
+/// `cargo install --list`: "name v1.2.3:" or "name v1.2.3 (source):" followed by indented binaries
+fn parse_cargo_install_list(listing: &str) -> Vec<Package> {
+    let mut packages: Vec<Package> = Vec::new();
+    for line in listing.lines() {
+        if line.starts_with(char::is_whitespace) {
+            if let Some(package) = packages.last_mut() {
+                package.binaries.push(line.trim().to_string());
+            }
+            continue;
+        }
+        let Some(header) = line.trim().strip_suffix(':') else { continue };
+        let mut parts = header.split_whitespace();
+        let Some(name) = parts.next() else { continue };
+        let mut package = Package::new("cargo", name.to_string());
+        package.version = parts.next().map(|v| v.trim_start_matches('v').to_string());
+        packages.push(package);
+    }
+    packages
+}
+
+/// Python applications installed with pipx
+pub struct PipxSource;
+
+#[derive(Debug, Deserialize)]
+struct PipxList {
+    #[serde(default)]
+    venvs: BTreeMap<String, PipxVenv>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PipxVenv {
+    metadata: PipxMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+struct PipxMetadata {
+    main_package: PipxPackage,
+}
+
+#[derive(Debug, Deserialize)]
+struct PipxPackage {
+    package: String,
+    package_version: Option<String>,
+    #[serde(default)]
+    apps: Vec<String>,
+}
+
+fn pipx_bin_dir() -> Option<PathBuf> {
+    match std::env::var_os("PIPX_BIN_DIR") {
+        Some(dir) => Some(PathBuf::from(dir)),
+        None => dirs::home_dir().map(|h| h.join(".local/bin")),
+    }
+}
+
+impl PackageSource for PipxSource {
+    fn name(&self) -> &'static str {
+        "pipx"
+    }
+
+    fn is_available(&self) -> bool {
+        tool_available("pipx", "--version")
+    }
+
+    fn installed_packages(&self) -> Result<Vec<Package>> {
+        parse_pipx_list(&command_stdout("pipx", &["list", "--json"])?)
+    }
+
+    fn package_commands(&self, package: &Package) -> Vec<PackageCommand> {
+        commands_in_bin_dir(&package.binaries, pipx_bin_dir().as_deref())
+    }
+}
+
+fn parse_pipx_list(json: &str) -> Result<Vec<Package>> {
+    let list: PipxList = serde_json::from_str(json)?;
+    Ok(list
+        .venvs
+        .into_values()
+        .map(|venv| {
+            let main = venv.metadata.main_package;
+            let mut package = Package::new("pipx", main.package);
+            package.version = main.package_version;
+            package.binaries = main.apps;
+            package
+        })
+        .collect())
+}
+
+/// Globally installed npm packages (`npm install -g`)
+pub struct NpmSource;
+
+#[derive(Debug, Deserialize)]
+struct NpmList {
+    #[serde(default)]
+    dependencies: BTreeMap<String, NpmDependency>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NpmDependency {
+    version: Option<String>,
+}
+
+/// The parts of a package.json the inventory uses
+#[derive(Debug, Default, Deserialize)]
+struct NpmManifest {
+    description: Option<String>,
+    homepage: Option<String>,
+    bin: Option<serde_json::Value>,
+}
+
+impl PackageSource for NpmSource {
+    fn name(&self) -> &'static str {
+        "npm"
+    }
+
+    fn is_available(&self) -> bool {
+        tool_available("npm", "--version")
+    }
+
+    fn installed_packages(&self) -> Result<Vec<Package>> {
+        let list: NpmList = serde_json::from_str(&command_stdout("npm", &["ls", "-g", "--depth=0", "--json"])?)?;
+        let root = command_stdout("npm", &["root", "-g"]).map(|r| PathBuf::from(r.trim())).ok();
+
+        Ok(list
+            .dependencies
+            .into_iter()
+            .map(|(name, dep)| {
+                // Descriptions and binaries are only in each package's own manifest
+                let manifest = root
+                    .as_ref()
+                    .and_then(|root| std::fs::read(root.join(&name).join("package.json")).ok())
+                    .and_then(|bytes| serde_json::from_slice::<NpmManifest>(&bytes).ok())
+                    .unwrap_or_default();
+                let mut package = Package::new("npm", name);
+                package.version = dep.version;
+                package.binaries = npm_binaries(&package.name, manifest.bin.as_ref());
+                package.desc = manifest.description;
+                package.homepage = manifest.homepage;
+                package
+            })
+            .collect())
+    }
+
+    fn package_commands(&self, package: &Package) -> Vec<PackageCommand> {
+        // npm links binaries into its global bin directory, which is on PATH
+        commands_in_bin_dir(&package.binaries, None)
+    }
+}
+
+/// package.json "bin" is either a path (command named after the package, without
+/// its scope) or a map of command name -> path
+fn npm_binaries(package_name: &str, bin: Option<&serde_json::Value>) -> Vec<String> {
+    match bin {
+        Some(serde_json::Value::String(_)) => {
+            vec![package_name.rsplit('/').next().unwrap_or(package_name).to_string()]
+        }
+        Some(serde_json::Value::Object(map)) => map.keys().cloned().collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Packages in the user's nix profile (`nix profile install`)
+pub struct NixSource;
+
+impl PackageSource for NixSource {
+    fn name(&self) -> &'static str {
+        "nix"
+    }
+
+    fn is_available(&self) -> bool {
+        tool_available("nix", "--version")
+    }
+
+    fn installed_packages(&self) -> Result<Vec<Package>> {
+        parse_nix_profile(&command_stdout("nix", &["profile", "list", "--json"])?)
+    }
+
+    fn package_commands(&self, package: &Package) -> Vec<PackageCommand> {
+        package.location.as_deref().map(commands_in_prefix).unwrap_or_default()
+    }
+}
+
+/// `nix profile list --json`; "elements" is a map keyed by name in newer versions
+/// of nix and a list in older ones
+fn parse_nix_profile(json: &str) -> Result<Vec<Package>> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    let elements: Vec<(Option<String>, &serde_json::Value)> = match value.get("elements") {
+        Some(serde_json::Value::Object(map)) => map.iter().map(|(k, v)| (Some(k.clone()), v)).collect(),
+        Some(serde_json::Value::Array(list)) => list.iter().map(|v| (None, v)).collect(),
+        _ => Vec::new(),
+    };
+
+    Ok(elements
+        .into_iter()
+        .filter(|(_, element)| element.get("active").and_then(|a| a.as_bool()).unwrap_or(true))
+        .filter_map(|(key, element)| {
+            let store_path = element.get("storePaths")?.as_array()?.first()?.as_str()?;
+            // /nix/store/<hash>-ripgrep-14.1.0 -> ("ripgrep", "14.1.0")
+            let full = store_path.rsplit('/').next()?.split_once('-')?.1;
+            let (base, version) = match full.rsplit_once('-') {
+                Some((base, version)) if version.starts_with(|c: char| c.is_ascii_digit()) => (base, Some(version)),
+                _ => (full, None),
+            };
+            let attr_name = element
+                .get("attrPath")
+                .and_then(|a| a.as_str())
+                .and_then(|a| a.rsplit('.').next());
+            let name = key.as_deref().or(attr_name).unwrap_or(base);
+
+            let mut package = Package::new("nix", name.to_string());
+            package.version = version.map(str::to_string);
+            package.location = Some(PathBuf::from(store_path));
+            Some(package)
+        })
+        .collect())
+}
+
+/// Executables on $PATH, including system tools and manual installs no package manager
+/// knows about. Each PATH directory is reported as one package; commands another source
+/// installs are skipped at indexing time (see `indexer::index_packages`).
+pub struct PathSource;
+
+impl PackageSource for PathSource {
+    fn name(&self) -> &'static str {
+        PATH_SOURCE
+    }
+
+    fn is_available(&self) -> bool {
+        std::env::var_os("PATH").is_some()
+    }
+
+    fn installed_packages(&self) -> Result<Vec<Package>> {
+        Ok(scan_path(&std::env::var_os("PATH").unwrap_or_default()))
+    }
+
+    /// Commands by name only, so documentation is looked up the way a shell would
+    /// run them: the first match on PATH. Administration tools in sbin directories
+    /// are never run, only their man pages read.
+    fn package_commands(&self, package: &Package) -> Vec<PackageCommand> {
+        let is_sbin = package
+            .location
+            .as_deref()
+            .and_then(Path::file_name)
+            .is_some_and(|name| name == "sbin");
+        let help_options = if is_sbin { &[] } else { PATH_HELP_OPTIONS };
+        package
+            .binaries
+            .iter()
+            .map(|name| PackageCommand { help_options, ..PackageCommand::named(name) })
+            .collect()
+    }
+}
+
+/// One package per PATH directory with the executables it contributes. Names shadowed
+/// by an earlier directory aren't runnable by name and are left out, as are relative
+/// entries (".") and directories listed twice. The version is a hash of the names and
+/// modification times of those executables, so incremental builds only read a
+/// directory again after something in it was installed, upgraded or removed.
+fn scan_path(path: &OsStr) -> Vec<Package> {
+    let mut seen_dirs = HashSet::new();
+    let mut seen_names = HashSet::new();
+    let mut packages = Vec::new();
+
+    for dir in std::env::split_paths(path) {
+        if !dir.is_absolute() || !seen_dirs.insert(dir.canonicalize().unwrap_or_else(|_| dir.clone())) {
+            continue;
+        }
+        let mut entries = dir_entries(&dir);
+        entries.sort();
+        let binaries: Vec<String> = entries
+            .iter()
+            .filter(|path| is_executable(path))
+            .filter_map(|path| path.file_name()?.to_str().map(str::to_string))
+            .filter(|name| seen_names.insert(name.clone()))
+            .collect();
+        if binaries.is_empty() {
+            continue;
+        }
+
+        let mut package = Package::new(PATH_SOURCE, dir.display().to_string());
+        package.version = Some(directory_version(&dir, &binaries));
+        package.binaries = binaries;
+        package.location = Some(dir);
+        packages.push(package);
+    }
+    packages
+}
+
+fn directory_version(dir: &Path, binaries: &[String]) -> String {
+    let stamps: Vec<String> = binaries
+        .iter()
+        .map(|name| {
+            let modified = std::fs::metadata(dir.join(name))
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|d| d.as_nanos())
+                .unwrap_or(0);
+            format!("{}@{}", name, modified)
+        })
+        .collect();
+    crate::kb_builder::content_hash(stamps.iter().map(String::as_str))
+}
+
+/// Count of packages per source, in ALL_SOURCES order, for status output
+pub fn counts_by_source(packages: &[Package]) -> Vec<(String, usize)> {
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for p in packages {
+        *counts.entry(p.source.as_str()).or_default() += 1;
+    }
+    let mut ordered: Vec<(String, usize)> = counts.into_iter().map(|(s, n)| (s.to_string(), n)).collect();
+    ordered.sort_by_key(|(s, _)| ALL_SOURCES.iter().position(|a| a == s).unwrap_or(usize::MAX));
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{touch, TempDir};
+
+    #[test]
+    fn test_commands_in_prefix_merges_binaries_and_man_pages() {
+        let prefix = TempDir::new("prefix");
+        touch(&prefix.join("bin/gls"), 0o755);
+        touch(&prefix.join("bin/gcp"), 0o755);
+        touch(&prefix.join("bin/README"), 0o644);
+        touch(&prefix.join("share/man/man1/gls.1"), 0o644);
+        touch(&prefix.join("share/man/man8/gchroot.8.gz"), 0o644);
+        touch(&prefix.join("share/man/man5/gconf.5"), 0o644);
+
+        let commands = commands_in_prefix(&prefix);
+        let names: Vec<&str> = commands.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["gchroot", "gcp", "gls"]);
+        assert_eq!(commands[2].executable, Some(prefix.join("bin/gls")));
+        assert_eq!(commands[2].man_page, Some(prefix.join("share/man/man1/gls.1")));
+        assert!(commands[0].executable.is_none());
+    }
+
+    #[test]
+    fn test_man_page_name() {
+        assert_eq!(man_page_name("ls.1").as_deref(), Some("ls"));
+        assert_eq!(man_page_name("python3.12.1.gz").as_deref(), Some("python3.12"));
+        assert_eq!(man_page_name("README"), None);
+        assert_eq!(man_page_name("notes.txt"), None);
+    }
+
+    #[test]
+    fn test_parse_dpkg_keeps_installed_and_marks_manual() {
+        let listing = "ii \tripgrep\t13.0.0-4\tsearch tool\nrc \told-pkg\t1.0\tremoved\nii \tlibc6\t2.36\tGNU C Library\n";
+        let packages = parse_dpkg(listing, Some("ripgrep\n"));
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].source, "apt");
+        assert_eq!(packages[0].version.as_deref(), Some("13.0.0-4"));
+        assert_eq!(packages[0].desc.as_deref(), Some("search tool"));
+        assert!(packages[0].installed_on_request);
+        assert!(!packages[1].installed_on_request);
+        assert!(parse_dpkg(listing, None)[1].installed_on_request);
+    }
+
+    #[test]
+    fn test_parse_cargo_install_list() {
+        let listing = "bat v0.24.0:\n    bat\nripgrep v14.1.0 (https://github.com/BurntSushi/ripgrep):\n    rg\nxsv v0.13.0:\n    xsv\n";
+        let packages = parse_cargo_install_list(listing);
+        assert_eq!(packages.len(), 3);
+        assert_eq!(packages[1].name, "ripgrep");
+        assert_eq!(packages[1].version.as_deref(), Some("14.1.0"));
+        assert_eq!(packages[1].binaries, vec!["rg"]);
+    }
+
+    #[test]
+    fn test_parse_pipx_list() {
+        let json = r#"{"pipx_spec_version": "0.1", "venvs": {"black": {"metadata": {"main_package": {
+            "package": "black", "package_version": "23.12.1", "apps": ["black", "blackd"]}}}}}"#;
+        let packages = parse_pipx_list(json).unwrap();
+        assert_eq!(packages[0].name, "black");
+        assert_eq!(packages[0].binaries, vec!["black", "blackd"]);
+        assert_eq!(packages[0].source, "pipx");
+    }
+
+    #[test]
+    fn test_npm_binaries() {
+        let single = serde_json::json!("bin/tsc.js");
+        assert_eq!(npm_binaries("@scope/tool", Some(&single)), vec!["tool"]);
+        let map = serde_json::json!({"tsc": "bin/tsc", "tsserver": "bin/tsserver"});
+        assert_eq!(npm_binaries("typescript", Some(&map)), vec!["tsc", "tsserver"]);
+        assert!(npm_binaries("x", None).is_empty());
+    }
+
+    #[test]
+    fn test_parse_nix_profile_both_formats() {
+        let v3 = r#"{"version": 3, "elements": {"ripgrep": {"active": true, "attrPath": "legacyPackages.x86_64-linux.ripgrep",
+            "storePaths": ["/nix/store/abc123-ripgrep-14.1.0"]}}}"#;
+        let packages = parse_nix_profile(v3).unwrap();
+        assert_eq!(packages[0].name, "ripgrep");
+        assert_eq!(packages[0].version.as_deref(), Some("14.1.0"));
+        assert_eq!(packages[0].location, Some(PathBuf::from("/nix/store/abc123-ripgrep-14.1.0")));
+
+        let v2 = r#"{"version": 2, "elements": [{"attrPath": "legacyPackages.aarch64-darwin.fd",
+            "storePaths": ["/nix/store/def456-fd-9.0.0"]}]}"#;
+        assert_eq!(parse_nix_profile(v2).unwrap()[0].name, "fd");
+    }
+
+    #[test]
+    fn test_scan_path_skips_shadowed_and_non_executables() {
+        let root = TempDir::new("prefix");
+        touch(&root.join("local/rg"), 0o755);
+        touch(&root.join("local/ls"), 0o755);
+        touch(&root.join("usr/ls"), 0o755);
+        touch(&root.join("usr/tar"), 0o755);
+        touch(&root.join("usr/notes.txt"), 0o644);
+        std::fs::create_dir_all(root.join("empty")).unwrap();
+
+        let dirs = ["local", "usr", "local", "empty"].map(|d| root.join(d));
+        let mut entries: Vec<PathBuf> = dirs.to_vec();
+        entries.insert(0, PathBuf::from("."));
+        let path = std::env::join_paths(entries).unwrap();
+        let packages = scan_path(&path);
+
+        assert_eq!(packages.len(), 2);
+        assert_eq!(packages[0].source, PATH_SOURCE);
+        assert_eq!(packages[0].binaries, vec!["ls", "rg"]);
+        assert_eq!(packages[1].binaries, vec!["tar"]);
+        assert_eq!(packages[1].location, Some(root.join("usr")));
+        assert!(prompt_labels(&packages).is_empty());
+
+        let commands = PathSource.package_commands(&packages[1]);
+        assert_eq!(commands, vec![PackageCommand { help_options: &["--help"], ..PackageCommand::named("tar") }]);
+
+        // Same contents, same version; a new executable changes it
+        let version = packages[1].version.clone().unwrap();
+        assert_eq!(scan_path(&path)[1].version, Some(version.clone()));
+        touch(&root.join("usr/zstd"), 0o755);
+        assert_ne!(scan_path(&path)[1].version, Some(version));
+
+        touch(&root.join("sbin/shutdown"), 0o755);
+        let sbin = &scan_path(root.join("sbin").as_os_str())[0];
+        assert!(PathSource.package_commands(sbin)[0].help_options.is_empty());
+    }
+
+    #[test]
+    fn test_unknown_sources_are_skipped() {
+        assert!(source_by_name("pacman").is_none());
+        assert!(available_sources(&["pacman".to_string()]).is_empty());
+        let mut a = Package::new("cargo", "bat".to_string());
+        a.installed_on_request = false;
+        let b = Package::new("brew", "jq".to_string());
+        let counts = counts_by_source(&[a, b]);
+        assert_eq!(counts, vec![("brew".to_string(), 1), ("cargo".to_string(), 1)]);
+    }
+}
@@ -1,10 +1,30 @@
 use anyhow::Result;
-use crate::ollama::OllamaClient;
-use crate::vector_store::VectorStore;
+use crate::chunker::MAX_CHUNK_CHARS;
+use crate::ollama::{ChatMessage, OllamaClient};
+use crate::vector_store::{StoredCommand, VectorStore};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Standard RRF damping constant: keeps one list's top hit from dominating the fusion
+const RRF_K: f32 = 60.0;
+/// Each ranking contributes this many times `top_k` candidates to the fusion
+const CANDIDATES_PER_RESULT: usize = 4;
+
+/// Result of a RAG query: the answer plus what was retrieved to produce it
+pub struct RagAnswer {
+    pub answer: String,
+    /// Retrieved commands with their similarity scores, best first
+    pub retrieved: Vec<(f32, StoredCommand)>,
+    pub retrieval_time: Duration,
+    pub generation_time: Duration,
+}
 
 pub struct RagPipeline<'a> {
     vector_store: &'a VectorStore,
     ollama_client: &'a OllamaClient,
+    // Documents scoring below this are treated as irrelevant
+    min_similarity: f32,
 }
 
 impl<'a> RagPipeline<'a> {
@@ -12,29 +32,78 @@ impl<'a> RagPipeline<'a> {
         Self {
             vector_store,
             ollama_client,
+            min_similarity: f32::MIN,
         }
     }
 
-    /// Retrieve relevant context for a user query
-    pub async fn retrieve_context(&self, query: &str, top_k: usize) -> Result<String> {
+    /// Only use retrieved documents with at least this cosine similarity
+    pub fn with_min_similarity(mut self, min_similarity: f32) -> Self {
+        self.min_similarity = min_similarity;
+        self
+    }
+
+    /// Retrieve the commands most relevant to a user query, with their cosine similarity.
+    /// Embedding search and full-text BM25 search are ranked separately and merged with
+    /// reciprocal rank fusion, so exact tool names and flags are found even when the
+    /// embedding ranks them low. Hits from either ranking must reach the similarity
+    /// threshold; empty when nothing does.
+    pub async fn retrieve(&self, query: &str, top_k: usize) -> Result<Vec<(f32, StoredCommand)>> {
         // Generate embedding for the query
         let query_embedding = self.ollama_client.generate_embedding(query).await?;
+        self.retrieve_with_embedding(query, &query_embedding, top_k)
+    }
+
+    /// `retrieve` with the query's embedding already computed
+    fn retrieve_with_embedding(&self, query: &str, query_embedding: &[f32], top_k: usize) -> Result<Vec<(f32, StoredCommand)>> {
+        let candidates = top_k * CANDIDATES_PER_RESULT;
+        let semantic = self.vector_store.search_similar(query_embedding, candidates, self.min_similarity)?;
+        let mut scores: HashMap<i64, f32> = semantic.iter().map(|(score, cmd)| (cmd.id, *score)).collect();
+
+        // Lexical hits are held to the same threshold, so a stray word match can't
+        // override the "nothing relevant" fallback
+        let mut lexical = Vec::new();
+        for cmd in self.vector_store.search_lexical(query, candidates)? {
+            let score = match scores.get(&cmd.id) {
+                Some(score) => *score,
+                None => self.vector_store.similarity(cmd.id, query_embedding)?.unwrap_or(0.0),
+            };
+            if score >= self.min_similarity {
+                scores.insert(cmd.id, score);
+                lexical.push(cmd);
+            }
+        }
+
+        let semantic_ids: Vec<i64> = semantic.iter().map(|(_, cmd)| cmd.id).collect();
+        let lexical_ids: Vec<i64> = lexical.iter().map(|cmd| cmd.id).collect();
+        let fused = reciprocal_rank_fusion(&[semantic_ids, lexical_ids]);
+
+        let mut docs: HashMap<i64, StoredCommand> = semantic
+            .into_iter()
+            .map(|(_, cmd)| (cmd.id, cmd))
+            .chain(lexical.into_iter().map(|cmd| (cmd.id, cmd)))
+            .collect();
+
+        let mut results = Vec::with_capacity(top_k);
+        for (_, id) in fused.into_iter().take(top_k) {
+            let (Some(cmd), Some(score)) = (docs.remove(&id), scores.get(&id)) else { continue };
+            results.push((*score, cmd));
+        }
+        Ok(results)
+    }
 
-        // Search for similar commands
-        let similar_commands = self.vector_store.search_similar(&query_embedding, top_k)?;
-
-        // Format the context
-        let mut context = String::new();
-        for (i, cmd) in similar_commands.iter().enumerate() {
-            context.push_str(&format!(
-                "--- Command {}: {} ---\n{}\n\n",
-                i + 1,
-                cmd.command_name,
-                truncate_text(&cmd.man_content, 500)
-            ));
+    /// Retrieve relevant context for a user query; None when nothing is relevant enough
+    pub async fn retrieve_context(&self, query: &str, top_k: usize) -> Result<Option<String>> {
+        let similar_commands = self.retrieve(query, top_k).await?;
+        if similar_commands.is_empty() {
+            crate::log::log_info("No retrieved documents above the similarity threshold; answering without RAG");
+            return Ok(None);
         }
+        Ok(Some(self.format_context(&similar_commands)?))
+    }
 
-        Ok(context)
+    /// Format retrieved commands, with their package descriptions, as the documentation section of a prompt
+    fn format_context(&self, commands: &[(f32, StoredCommand)]) -> Result<String> {
+        Ok(format_context(commands, &self.vector_store.package_descriptions()?))
     }
 
     /// Query with RAG - retrieve context and generate response using langchain-rust chain pattern
@@ -43,57 +112,168 @@ impl<'a> RagPipeline<'a> {
         user_query: &str,
         packages: &[String],
         top_k: usize,
-    ) -> Result<String> {
+    ) -> Result<RagAnswer> {
         // Check if vector store has data
         if self.vector_store.is_empty()? {
             // Fall back to query without RAG
-            return self.ollama_client.query(user_query, packages, None).await;
+            let started = Instant::now();
+            let answer = self.ollama_client.query(user_query, packages, None).await?;
+            return Ok(RagAnswer {
+                answer,
+                retrieved: Vec::new(),
+                retrieval_time: Duration::ZERO,
+                generation_time: started.elapsed(),
+            });
         }
 
         // Retrieve relevant context using RAG pattern
-        let context = self.retrieve_context(user_query, top_k).await?;
+        let started = Instant::now();
+        let retrieved = self.retrieve(user_query, top_k).await?;
+        let retrieval_time = started.elapsed();
 
-        // Build the final prompt using langchain-like chain composition
-        let _final_prompt = self.build_rag_prompt(user_query, packages, &context);
+        // Nothing relevant enough: answer without documentation rather than with noise
+        let context = if retrieved.is_empty() { None } else { Some(self.format_context(&retrieved)?) };
+        if context.is_none() {
+            crate::log::log_info("No retrieved documents above the similarity threshold; answering without RAG");
+        }
 
         // Query with context using Ollama
-        self.ollama_client
-            .query(user_query, packages, Some(&context))
-            .await
-    }
+        let started = Instant::now();
+        let answer = self
+            .ollama_client
+            .query(user_query, packages, context.as_deref())
+            .await?;
 
-    /// Build RAG prompt following langchain pattern
-    fn build_rag_prompt(&self, user_query: &str, packages: &[String], context: &str) -> String {
-        format!(
-            r#"You are a helpful assistant that recommends command-line tools based on user needs.
+        Ok(RagAnswer {
+            answer,
+            retrieved,
+            retrieval_time,
+            generation_time: started.elapsed(),
+        })
+    }
 
-Available tools installed via Homebrew:
-{}
+    /// One conversational turn with RAG: context is retrieved for this turn and sent
+    /// along with the prior turns in `history`; generated chunks go to `chunk_tx`.
+    pub async fn chat_with_rag_stream(
+        &self,
+        history: &[ChatMessage],
+        user_query: &str,
+        packages: &[String],
+        top_k: usize,
+        chunk_tx: &UnboundedSender<String>,
+    ) -> Result<String> {
+        if self.vector_store.is_empty()? {
+            return self.ollama_client.chat_stream(history, user_query, packages, None, chunk_tx).await;
+        }
 
-Relevant documentation from installed tools:
-{}
+        // Follow-ups like "now do it recursively" say little on their own, so retrieve
+        // with the previous question included
+        let retrieval_query = match history.iter().rev().find(|m| m.role == "user") {
+            Some(prev) => format!("{}\n{}", prev.content, user_query),
+            None => user_query.to_string(),
+        };
+        let context = self.retrieve_context(&retrieval_query, top_k).await?;
 
-User query: {}
+        self.ollama_client
+            .chat_stream(history, user_query, packages, context.as_deref(), chunk_tx)
+            .await
+    }
+}
 
-Please recommend the most suitable tool(s) from the available list and provide:
-1. The tool name
-2. A brief description of what it does
-3. A practical usage example with command-line syntax
-4. The specific use case scenario
+/// Merge several best-first rankings of row ids: each id scores 1 / (RRF_K + rank)
+/// in every list it appears in. Returns (fused score, id), best first.
+fn reciprocal_rank_fusion(rankings: &[Vec<i64>]) -> Vec<(f32, i64)> {
+    let mut fused: HashMap<i64, f32> = HashMap::new();
+    for ranking in rankings {
+        for (rank, id) in ranking.iter().enumerate() {
+            *fused.entry(*id).or_default() += 1.0 / (RRF_K + rank as f32 + 1.0);
+        }
+    }
+    let mut fused: Vec<(f32, i64)> = fused.into_iter().map(|(id, score)| (score, id)).collect();
+    // Ties (same ranks in different lists) fall back to id order so results are stable
+    fused.sort_by(|a, b| b.0.total_cmp(&a.0).then(a.1.cmp(&b.1)));
+    fused
+}
 
-Format your response clearly and concisely."#,
-            packages.join(", "),
-            context,
-            user_query
-        )
+/// Format retrieved commands as the documentation section of a prompt
+fn format_context(commands: &[(f32, StoredCommand)], descriptions: &HashMap<String, String>) -> String {
+    let mut context = String::new();
+    for (i, (_, cmd)) in commands.iter().enumerate() {
+        let mut title = if cmd.section.is_empty() {
+            cmd.command_name.clone()
+        } else {
+            format!("{} ({})", cmd.command_name, cmd.section)
+        };
+        if let Some(desc) = descriptions.get(&cmd.package_name) {
+            title.push_str(&format!(" from {}: {}", cmd.package_name, desc));
+        }
+        context.push_str(&format!(
+            "--- Command {}: {} ---\n{}\n\n",
+            i + 1,
+            title,
+            truncate_text(&cmd.man_content, MAX_CHUNK_CHARS)
+        ));
     }
+    context
 }
 
-/// Truncate text to a maximum length
+/// Truncate text to at most `max_len` bytes, backing off to a char boundary
 fn truncate_text(text: &str, max_len: usize) -> String {
     if text.len() <= max_len {
         text.to_string()
     } else {
-        format!("{}...", &text[..max_len])
+        let mut end = max_len;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}...", &text[..end])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunker::Chunk;
+
+    #[test]
+    fn test_rrf_rewards_agreement_between_rankings() {
+        // 3 is mediocre in both lists but beats the items only one list likes
+        let fused = reciprocal_rank_fusion(&[vec![1, 3, 4], vec![2, 3, 5]]);
+        let ids: Vec<i64> = fused.iter().map(|(_, id)| *id).collect();
+        assert_eq!(ids[0], 3);
+        assert_eq!(&ids[1..3], &[1, 2]);
+        assert_eq!(ids.len(), 5);
+    }
+
+    #[test]
+    fn test_truncate_text_respects_char_boundaries() {
+        assert_eq!(truncate_text("héllo", 2), "h...");
+        assert_eq!(truncate_text("abc", 5), "abc");
+    }
+
+    #[test]
+    fn test_rrf_single_ranking_keeps_order() {
+        let fused = reciprocal_rank_fusion(&[vec![9, 7, 8], vec![]]);
+        assert_eq!(fused.iter().map(|(_, id)| *id).collect::<Vec<_>>(), vec![9, 7, 8]);
+        assert!(reciprocal_rank_fusion(&[vec![], vec![]]).is_empty());
+    }
+
+    #[test]
+    fn test_lexical_hits_below_threshold_are_dropped() {
+        let dir = crate::test_support::TempDir::new("rag");
+        let vs = VectorStore::new(dir.join("commands.db")).unwrap();
+        let chunk = |content: &str| Chunk { section: "NAME".into(), content: content.into() };
+        vs.replace_command("findutils", "find", "h1", &[(chunk("find - search for files in a directory hierarchy"), vec![1.0, 0.0])])
+            .unwrap();
+        // Matches the question's words but not its meaning
+        vs.replace_command("coreutils", "du", "h2", &[(chunk("du - estimate file space usage of large files"), vec![0.0, 1.0])])
+            .unwrap();
+        let ollama = OllamaClient::new("unused".to_string());
+        let rag = RagPipeline::new(&vs, &ollama).with_min_similarity(0.5);
+
+        let hits = rag.retrieve_with_embedding("large files", &[1.0, 0.1], 2).unwrap();
+        assert_eq!(hits.iter().map(|(_, c)| c.command_name.as_str()).collect::<Vec<_>>(), vec!["find"]);
+        // Nothing close enough: no RAG context at all, despite the word matches
+        assert!(rag.retrieve_with_embedding("large files", &[-1.0, -1.0], 2).unwrap().is_empty());
     }
 }
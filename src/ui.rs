@@ -27,7 +27,10 @@ pub fn render(f: &mut Frame, app: &App) {
 fn render_input(f: &mut Frame, app: &App, area: Rect) {
     let input_text = match app.state {
         AppState::Input => format!("{}_", app.input),
-        AppState::Loading => "Loading...".to_string(),
+        AppState::Loading => {
+            const SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+            format!("{} Loading... (Esc to cancel)", SPINNER[app.spinner_frame % SPINNER.len()])
+        }
     };
 
     let style = match app.state {
@@ -66,7 +69,7 @@ fn render_status(f: &mut Frame, app: &App, area: Rect) {
 }
 
 fn render_response(f: &mut Frame, app: &App, area: Rect) {
-    let text = if app.response.is_empty() {
+    let text = if app.conversation.is_empty() && app.pending_query.is_none() && app.response.is_empty() {
         Text::from(vec![
             Line::from(""),
             Line::from(Span::styled(
@@ -80,12 +83,24 @@ fn render_response(f: &mut Frame, app: &App, area: Rect) {
             )),
             Line::from(""),
             Line::from(Span::styled(
-                "Press 'q' to quit, 'ctrl + r' to rebuild, ↑↓ to scroll",
+                "Press 'ctrl + q' to quit, 'ctrl + r' to rebuild, 'ctrl + n' for a new conversation, ↑↓ to scroll",
                 Style::default().fg(Color::Gray),
             )),
         ])
     } else {
-        Text::from(app.response.clone())
+        let mut lines: Vec<Line> = Vec::new();
+        for msg in &app.conversation {
+            if msg.role == "user" {
+                push_question(&mut lines, &msg.content);
+            } else {
+                push_answer(&mut lines, &msg.content);
+            }
+        }
+        if let Some(query) = &app.pending_query {
+            push_question(&mut lines, query);
+        }
+        push_answer(&mut lines, &app.response);
+        Text::from(lines)
     };
 
     let paragraph = Paragraph::new(text)
@@ -95,12 +110,34 @@ fn render_response(f: &mut Frame, app: &App, area: Rect) {
                 .borders(Borders::ALL)
                 .border_style(Style::default().fg(Color::Cyan)),
         )
-        .wrap(Wrap { trim: true })
-        .scroll((app.scroll_offset, 0));
+        .wrap(Wrap { trim: true });
+
+    // Track how far the response can scroll so streaming answers can stay pinned to the bottom
+    let line_count = paragraph.line_count(area.width.saturating_sub(2));
+    let max_scroll = u16::try_from(line_count.saturating_sub(area.height as usize)).unwrap_or(u16::MAX);
+    app.response_max_scroll.set(max_scroll);
+
+    let offset = if app.follow_response { max_scroll } else { app.scroll_offset };
+    let paragraph = paragraph.scroll((offset, 0));
 
     f.render_widget(paragraph, area);
 }
 
+fn push_question(lines: &mut Vec<Line<'static>>, question: &str) {
+    lines.push(Line::from(Span::styled(
+        format!("> {}", question),
+        Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+    )));
+}
+
+fn push_answer(lines: &mut Vec<Line<'static>>, answer: &str) {
+    if answer.is_empty() {
+        return;
+    }
+    lines.extend(answer.lines().map(|l| Line::from(l.to_string())));
+    lines.push(Line::from(""));
+}
+
 fn render_kb_progress(f: &mut Frame, app: &App, area: Rect) {
     let lines: Vec<Line> = if app.kb_progress.is_empty() {
         vec![Line::from(Span::styled("No build activity", Style::default().fg(Color::Gray)))]
@@ -1,19 +1,77 @@
 use anyhow::Result;
-use rusqlite::{params, Connection};
+use crate::chunker::Chunk;
+use crate::embedding_codec::{self, EmbeddingEncoding};
+use crate::indexer::ExtractionProblem;
+use crate::package_source::Package;
+use crate::embedding_matrix::EmbeddingMatrix;
+use crate::hnsw::HnswIndex;
+use rusqlite::{params, Connection, Transaction, TransactionBehavior};
 use serde::{Deserialize, Serialize};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+/// Below this many rows an exact scan is fast enough and always accurate
+const ANN_MIN_ROWS: usize = 1000;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct StoredCommand {
     pub id: i64,
     pub package_name: String,
     pub command_name: String,
+    /// Man page section this chunk came from, e.g. "OPTIONS --delete"; empty for a whole page
+    pub section: String,
     pub man_content: String,
     pub embedding: Vec<f32>,
 }
 
+/// A knowledge base build as recorded in `build_runs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildRun {
+    pub id: i64,
+    /// "running" (in progress, or interrupted if nothing is building), "failed",
+    /// "complete" or "abandoned" (superseded by a newer build)
+    pub status: String,
+    /// Whether the build started from an empty knowledge base rather than the current one
+    pub full: bool,
+    pub docs_done: usize,
+    pub docs_total: usize,
+    /// "package/command" of the last document stored
+    pub last_document: Option<String>,
+}
+
+/// Which embedding model produced the stored vectors, as recorded in `metadata`
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddingInfo {
+    pub model: String,
+    /// Length of the stored vectors; unknown until a build has embedded something
+    pub dimension: Option<usize>,
+    /// When the build that recorded this finished (UTC, "YYYY-MM-DD HH:MM:SS")
+    pub built_at: Option<String>,
+}
+
+const EMBEDDING_MODEL_KEY: &str = "embedding_model";
+const EMBEDDING_DIMENSION_KEY: &str = "embedding_dimension";
+const BUILT_AT_KEY: &str = "built_at";
+
+/// In-memory search state built from the database. Replaced as a whole on reload so
+/// concurrent searches always see a consistent snapshot.
+struct Resident {
+    matrix: EmbeddingMatrix,
+    // None if the persisted index is missing, unreadable or doesn't match `matrix`
+    ann: Option<HnswIndex>,
+}
+
+/// SQLite-backed store of command documents and their embeddings. Safe to share
+/// between tasks: the connection sits behind a mutex and embeddings are kept resident.
 pub struct VectorStore {
-    conn: Connection,
+    conn: Mutex<Connection>,
+    // HNSW index persisted next to the database (commands.hnsw)
+    ann_path: PathBuf,
+    // Loaded on first search and dropped whenever this store changes the data
+    resident: RwLock<Option<Arc<Resident>>>,
+    // How embeddings written through this store are encoded
+    encoding: EmbeddingEncoding,
 }
 
 impl VectorStore {
@@ -24,7 +82,8 @@ impl VectorStore {
             std::fs::create_dir_all(parent)?;
         }
 
-        let conn = Connection::open(db_path)?;
+        let ann_path = db_path.with_extension("hnsw");
+        let mut conn = Connection::open(&db_path)?;
         conn.busy_timeout(std::time::Duration::from_secs(5))?;
         
         // Performance optimization: enable WAL mode for better concurrent read performance
@@ -32,113 +91,347 @@ impl VectorStore {
         // Increase cache size to reduce disk I/O
         conn.execute_batch("PRAGMA cache_size = 10000")?;
         
-        // Create tables
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS commands (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                package_name TEXT NOT NULL,
-                command_name TEXT NOT NULL,
-                man_content TEXT NOT NULL,
-                embedding BLOB NOT NULL,
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )",
-            [],
-        )?;
+        migrate(&mut conn, &db_path)?;
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_package ON commands(package_name)",
-            [],
-        )?;
+        Ok(Self { conn: Mutex::new(conn), ann_path, resident: RwLock::new(None), encoding: EmbeddingEncoding::default() })
+    }
 
-        conn.execute(
-            "CREATE INDEX IF NOT EXISTS idx_command ON commands(command_name)",
-            [],
-        )?;
+    /// Encode embeddings stored from now on as `encoding` (f32 unless set)
+    pub fn set_embedding_encoding(&mut self, encoding: EmbeddingEncoding) {
+        self.encoding = encoding;
+    }
+
+    fn conn(&self) -> Result<MutexGuard<'_, Connection>> {
+        self.conn.lock().map_err(|_| anyhow::anyhow!("Vector store connection lock poisoned"))
+    }
 
-        Ok(Self { conn })
+    /// Replace the stored package metadata with `packages`
+    pub fn store_packages(&self, packages: &[Package]) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM packages", [])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT OR REPLACE INTO packages (name, source, kind, tap, version, description, homepage, installed_on_request)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for p in packages {
+                stmt.execute(params![
+                    p.name,
+                    p.source,
+                    p.kind.as_str(),
+                    p.tap,
+                    p.version,
+                    p.desc,
+                    p.homepage,
+                    p.installed_on_request
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
     }
 
-    /// Store a command with its embedding
-    pub fn store_command(
+    /// Replace the chunks of one command with `chunks` and record the hash of the
+    /// documentation they were made from, all in one transaction
+    pub fn replace_command(
         &self,
         package_name: &str,
         command_name: &str,
-        man_content: &str,
-        embedding: &[f32],
-    ) -> Result<i64> {
-        // Serialize embedding to bytes
-        let embedding_bytes = bincode::serialize(embedding)?;
-
-        self.conn.execute(
-            "INSERT INTO commands (package_name, command_name, man_content, embedding)
-             VALUES (?1, ?2, ?3, ?4)",
-            params![package_name, command_name, man_content, embedding_bytes],
+        content_hash: &str,
+        chunks: &[(Chunk, Vec<f32>)],
+    ) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        delete_commands(&tx, package_name, Some(command_name))?;
+        for (chunk, embedding) in chunks {
+            tx.execute(
+                "INSERT INTO commands (package_name, command_name, section, man_content, embedding, dimension, encoding)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    package_name,
+                    command_name,
+                    chunk.section,
+                    chunk.content,
+                    embedding_codec::encode(self.encoding, embedding),
+                    embedding.len() as i64,
+                    self.encoding.as_str()
+                ],
+            )?;
+            tx.execute(
+                "INSERT INTO commands_fts (rowid, command_name, man_content) VALUES (?1, ?2, ?3)",
+                params![tx.last_insert_rowid(), command_name, chunk.content],
+            )?;
+        }
+        tx.execute(
+            "INSERT INTO indexed_commands (package_name, command_name, content_hash) VALUES (?1, ?2, ?3)",
+            params![package_name, command_name, content_hash],
         )?;
+        tx.commit()?;
+        drop(conn);
 
-        Ok(self.conn.last_insert_rowid())
+        self.invalidate();
+        Ok(())
     }
 
-    /// Get all stored commands
-    pub fn get_all_commands(&self) -> Result<Vec<StoredCommand>> {
-        let mut stmt = self.conn.prepare(
-            "SELECT id, package_name, command_name, man_content, embedding FROM commands"
+    /// (package, command) -> hash of the documentation its stored chunks were made from
+    pub fn command_hashes(&self) -> Result<HashMap<(String, String), String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT package_name, command_name, content_hash FROM indexed_commands")?;
+        let hashes = stmt
+            .query_map([], |row| Ok(((row.get(0)?, row.get(1)?), row.get(2)?)))?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(hashes)
+    }
+
+    /// Package name -> the version whose commands are fully indexed
+    pub fn indexed_versions(&self) -> Result<HashMap<String, String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT name, version FROM indexed_packages")?;
+        let versions = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(versions)
+    }
+
+    /// Record that every command of this package version is indexed
+    pub fn mark_package_indexed(&self, name: &str, version: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO indexed_packages (name, version) VALUES (?1, ?2)",
+            params![name, version],
         )?;
+        Ok(())
+    }
+
+    /// Delete everything stored for packages not in `installed`. Returns how many packages were removed.
+    pub fn remove_packages_except(&self, installed: &HashSet<&str>) -> Result<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let stored: Vec<String> = tx
+            .prepare(
+                "SELECT package_name FROM commands UNION SELECT package_name FROM indexed_commands
+                 UNION SELECT name FROM indexed_packages",
+            )?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let removed: Vec<&String> = stored.iter().filter(|name| !installed.contains(name.as_str())).collect();
+        for name in &removed {
+            delete_commands(&tx, name, None)?;
+            tx.execute("DELETE FROM indexed_packages WHERE name = ?1", params![name])?;
+        }
+        tx.commit()?;
+        drop(conn);
 
+        if !removed.is_empty() {
+            self.invalidate();
+        }
+        Ok(removed.len())
+    }
+
+    /// Delete the commands of a package that aren't in `commands`, e.g. after an
+    /// upgrade dropped one. Returns how many commands were removed.
+    pub fn remove_commands_except(&self, package_name: &str, commands: &HashSet<String>) -> Result<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let stored: Vec<String> = tx
+            .prepare(
+                "SELECT command_name FROM commands WHERE package_name = ?1
+                 UNION SELECT command_name FROM indexed_commands WHERE package_name = ?1",
+            )?
+            .query_map(params![package_name], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        let removed: Vec<&String> = stored.iter().filter(|name| !commands.contains(*name)).collect();
+        for name in &removed {
+            delete_commands(&tx, package_name, Some(name))?;
+        }
+        tx.commit()?;
+        drop(conn);
+
+        if !removed.is_empty() {
+            self.invalidate();
+        }
+        Ok(removed.len())
+    }
+
+    /// Package name -> description, for packages that have one
+    pub fn package_descriptions(&self) -> Result<HashMap<String, String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT name, description FROM packages WHERE description IS NOT NULL AND description != ''")?;
+        let descriptions = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<HashMap<_, _>, _>>()?;
+        Ok(descriptions)
+    }
+
+    /// Search for similar commands, returning (cosine similarity, command) best first.
+    /// Hits scoring below `min_similarity` are dropped, so fewer than `top_k` (or none)
+    /// may come back. Scores come from the resident embeddings (HNSW for large stores,
+    /// exact scan otherwise); only the surviving hits are read back from SQLite.
+    pub fn search_similar(
+        &self,
+        query_embedding: &[f32],
+        top_k: usize,
+        min_similarity: f32,
+    ) -> Result<Vec<(f32, StoredCommand)>> {
+        let resident = self.resident()?;
+        let hits = match &resident.ann {
+            Some(index) if resident.matrix.len() >= ANN_MIN_ROWS => {
+                index.search(query_embedding, top_k, (top_k * 4).max(64))
+            }
+            _ => resident.matrix.search(query_embedding, top_k),
+        };
+
+        let mut results = Vec::with_capacity(hits.len());
+        for (score, id) in hits.into_iter().filter(|(score, _)| *score >= min_similarity) {
+            // A row deleted by another connection since the snapshot was taken is skipped
+            if let Some(cmd) = self.get_command(id)? {
+                results.push((score, cmd));
+            }
+        }
+        Ok(results)
+    }
+
+    /// Full-text search over command names and man pages, best BM25 match first.
+    /// Pages need only one word of the query, so natural-language questions match too;
+    /// punctuation such as `--` in flags is ignored.
+    pub fn search_lexical(&self, query: &str, top_k: usize) -> Result<Vec<StoredCommand>> {
+        let Some(fts_query) = fts_query(query) else {
+            return Ok(Vec::new());
+        };
+
+        let conn = self.conn()?;
+        // Name matches count ten times as much as matches in the page body
+        let mut stmt = conn.prepare_cached(
+            "SELECT c.id, c.package_name, c.command_name, c.man_content, c.embedding, c.section, c.dimension, c.encoding
+             FROM commands_fts f JOIN commands c ON c.id = f.rowid
+             WHERE commands_fts MATCH ?1
+             ORDER BY bm25(commands_fts, 10.0, 1.0)
+             LIMIT ?2",
+        )?;
         let commands = stmt
-            .query_map([], |row| {
-                let embedding_bytes: Vec<u8> = row.get(4)?;
-                let embedding: Vec<f32> = bincode::deserialize(&embedding_bytes)
-                    .map_err(|e| rusqlite::Error::FromSqlConversionFailure(
-                        4,
-                        rusqlite::types::Type::Blob,
-                        Box::new(e),
-                    ))?;
-
-                Ok(StoredCommand {
-                    id: row.get(0)?,
-                    package_name: row.get(1)?,
-                    command_name: row.get(2)?,
-                    man_content: row.get(3)?,
-                    embedding,
-                })
-            })?
+            .query_map(params![fts_query, top_k as i64], row_to_command)?
             .collect::<Result<Vec<_>, _>>()?;
-
         Ok(commands)
     }
 
-    /// Search for similar commands using cosine similarity
-    pub fn search_similar(&self, query_embedding: &[f32], top_k: usize) -> Result<Vec<StoredCommand>> {
-        let all_commands = self.get_all_commands()?;
-        
-        // Early return if no commands exist
-        if all_commands.is_empty() {
-            return Ok(Vec::new());
+    /// Cosine similarity between a stored command and a query, from the resident embeddings
+    pub fn similarity(&self, id: i64, query_embedding: &[f32]) -> Result<Option<f32>> {
+        Ok(self.resident()?.matrix.score(id, query_embedding))
+    }
+
+    /// Current resident snapshot, loading it from the database if needed
+    fn resident(&self) -> Result<Arc<Resident>> {
+        if let Some(resident) = self.resident.read().ok().and_then(|r| r.clone()) {
+            return Ok(resident);
         }
-        
-        let mut scored_commands: Vec<(f32, StoredCommand)> = all_commands
-            .into_iter()
-            .map(|cmd| {
-                let similarity = cosine_similarity(query_embedding, &cmd.embedding);
-                (similarity, cmd)
-            })
-            .collect();
+        self.load_resident()
+    }
+
+    /// Reload the resident embeddings and HNSW index from disk. Call after the
+    /// knowledge base was changed through another connection (e.g. a rebuild).
+    pub fn reload(&self) -> Result<()> {
+        self.load_resident().map(|_| ())
+    }
 
-        // Sort by similarity (descending)
-        // Use unwrap_or for safety in case of NaN values
-        scored_commands.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+    fn load_resident(&self) -> Result<Arc<Resident>> {
+        let matrix = self.load_matrix()?;
 
-        // Take top k
-        Ok(scored_commands
-            .into_iter()
-            .take(top_k)
-            .map(|(_, cmd)| cmd)
-            .collect())
+        let ann = match HnswIndex::load(&self.ann_path) {
+            Ok(index)
+                if index.len() == matrix.len()
+                    && index.max_id() == matrix.max_id()
+                    && index.dim() == matrix.dim() =>
+            {
+                Some(index)
+            }
+            Ok(_) => {
+                crate::log::log_info("HNSW index is out of date; using exact search");
+                None
+            }
+            Err(_) => None,
+        };
+
+        let resident = Arc::new(Resident { matrix, ann });
+        if let Ok(mut slot) = self.resident.write() {
+            *slot = Some(resident.clone());
+        }
+        Ok(resident)
+    }
+
+    /// Drop the resident snapshot so the next search reloads it
+    fn invalidate(&self) {
+        if let Ok(mut slot) = self.resident.write() {
+            *slot = None;
+        }
+    }
+
+    /// Read every embedding (but no document text) into a normalized matrix.
+    /// The dimension is taken from the first row; rows that differ are skipped.
+    /// An int8 knowledge base is kept quantized.
+    fn load_matrix(&self) -> Result<EmbeddingMatrix> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare("SELECT id, embedding, dimension, encoding FROM commands ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            let id: i64 = row.get(0)?;
+            let bytes: Vec<u8> = row.get(1)?;
+            let dimension: i64 = row.get(2)?;
+            let encoding: String = row.get(3)?;
+            Ok((id, bytes, dimension as usize, encoding))
+        })?;
+
+        let mut matrix: Option<EmbeddingMatrix> = None;
+        for row in rows {
+            let (id, bytes, dimension, encoding) = row?;
+            let encoding = EmbeddingEncoding::parse(&encoding)?;
+            let matrix = matrix.get_or_insert_with(|| match encoding {
+                EmbeddingEncoding::Int8 => EmbeddingMatrix::new_quantized(dimension),
+                _ => EmbeddingMatrix::new(dimension),
+            });
+            let pushed = match encoding {
+                EmbeddingEncoding::Int8 => {
+                    let (scale, values) = embedding_codec::split_int8(dimension, &bytes)?;
+                    matrix.push_quantized(id, scale, &values)
+                }
+                _ => matrix.push(id, &embedding_codec::decode(encoding, dimension, &bytes)?),
+            };
+            if !pushed {
+                crate::log::log_error(&format!("Skipping row {} in search: embedding dimension {}", id, dimension));
+            }
+        }
+        Ok(matrix.unwrap_or_else(|| EmbeddingMatrix::new(0)))
+    }
+
+    /// Build the HNSW index over all stored embeddings and persist it next to the database
+    pub fn build_ann_index(&self) -> Result<usize> {
+        let matrix = self.load_matrix()?;
+        if matrix.len() == 0 {
+            let _ = std::fs::remove_file(&self.ann_path);
+            self.invalidate();
+            return Ok(0);
+        }
+
+        let mut index = HnswIndex::new(matrix.dim());
+        for (i, &id) in matrix.ids().iter().enumerate() {
+            index.insert(id, &matrix.row(i))?;
+        }
+        index.save(&self.ann_path)?;
+        self.invalidate();
+        Ok(index.len())
+    }
+
+    /// Fetch a single command by row id
+    pub fn get_command(&self, id: i64) -> Result<Option<StoredCommand>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, package_name, command_name, man_content, embedding, section, dimension, encoding FROM commands WHERE id = ?1"
+        )?;
+        let mut rows = stmt.query_map(params![id], row_to_command)?;
+        Ok(rows.next().transpose()?)
     }
 
     /// Check if database is empty
     pub fn is_empty(&self) -> Result<bool> {
-        let count: i64 = self.conn.query_row(
+        let count: i64 = self.conn()?.query_row(
             "SELECT COUNT(*) FROM commands",
             [],
             |row| row.get(0),
@@ -146,9 +439,19 @@ impl VectorStore {
         Ok(count == 0)
     }
 
-    /// Get command count
+    /// Number of distinct commands (a command is stored as several chunks)
+    pub fn command_count(&self) -> Result<usize> {
+        let count: i64 = self.conn()?.query_row(
+            "SELECT COUNT(*) FROM (SELECT DISTINCT package_name, command_name FROM commands)",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Get stored document (chunk) count
     pub fn count(&self) -> Result<usize> {
-        let count: i64 = self.conn.query_row(
+        let count: i64 = self.conn()?.query_row(
             "SELECT COUNT(*) FROM commands",
             [],
             |row| row.get(0),
@@ -156,111 +459,965 @@ impl VectorStore {
         Ok(count as usize)
     }
 
-    /// Clear all commands from the store (used for rebuild)
-    pub fn clear(&mut self) -> Result<()> {
-        // Use a transaction for safety and performance
-        let tx = self.conn.transaction()?;
-        tx.execute("DELETE FROM commands", [])?;
+    /// Number of distinct packages with at least one indexed command
+    pub fn package_count(&self) -> Result<usize> {
+        let count: i64 = self.conn()?.query_row(
+            "SELECT COUNT(DISTINCT package_name) FROM commands",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(count as usize)
+    }
+
+    /// Record the start of a build; earlier builds that never finished are marked abandoned
+    pub fn start_build_run(&self, full: bool) -> Result<i64> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute("UPDATE build_runs SET status = 'abandoned' WHERE status IN ('running', 'failed')", [])?;
+        tx.execute("INSERT INTO build_runs (status, full) VALUES ('running', ?1)", params![full])?;
+        let id = tx.last_insert_rowid();
         tx.commit()?;
+        Ok(id)
+    }
+
+    /// The most recent build that didn't complete, if it wasn't superseded
+    pub fn incomplete_build_run(&self) -> Result<Option<BuildRun>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT id, status, full, docs_done, docs_total, last_document FROM build_runs
+             WHERE status IN ('running', 'failed') ORDER BY id DESC LIMIT 1",
+        )?;
+        let mut rows = stmt.query_map([], |row| {
+            Ok(BuildRun {
+                id: row.get(0)?,
+                status: row.get(1)?,
+                full: row.get(2)?,
+                docs_done: row.get::<_, i64>(3)? as usize,
+                docs_total: row.get::<_, i64>(4)? as usize,
+                last_document: row.get(5)?,
+            })
+        })?;
+        Ok(rows.next().transpose()?)
+    }
+
+    /// Record how far a build has got
+    pub fn checkpoint_build_run(&self, id: i64, docs_done: usize, docs_total: usize, last_document: &str) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE build_runs SET docs_done = ?2, docs_total = ?3, last_document = ?4, updated_at = CURRENT_TIMESTAMP
+             WHERE id = ?1",
+            params![id, docs_done as i64, docs_total as i64, last_document],
+        )?;
+        Ok(())
+    }
+
+    /// Set a build's status ("running" again when resumed, "complete" or "failed" when it ends)
+    pub fn set_build_run_status(&self, id: i64, status: &str) -> Result<()> {
+        self.conn()?.execute(
+            "UPDATE build_runs SET status = ?2, updated_at = CURRENT_TIMESTAMP WHERE id = ?1",
+            params![id, status],
+        )?;
         Ok(())
     }
+
+    fn metadata(&self, key: &str) -> Result<Option<String>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare_cached("SELECT value FROM metadata WHERE key = ?1")?;
+        let mut rows = stmt.query_map(params![key], |row| row.get(0))?;
+        Ok(rows.next().transpose()?)
+    }
+
+    /// Record the embedding model a build is using, before any vectors are stored
+    pub fn set_embedding_model(&self, model: &str) -> Result<()> {
+        self.conn()?.execute(
+            "INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)",
+            params![EMBEDDING_MODEL_KEY, model],
+        )?;
+        Ok(())
+    }
+
+    /// Record the model and vector dimension of a finished build, stamped with the current time
+    pub fn record_embedding_info(&self, model: &str, dimension: Option<usize>) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let mut stmt = tx.prepare("INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, ?2)")?;
+        stmt.execute(params![EMBEDDING_MODEL_KEY, model])?;
+        if let Some(dimension) = dimension {
+            stmt.execute(params![EMBEDDING_DIMENSION_KEY, dimension.to_string()])?;
+        }
+        tx.execute(
+            "INSERT OR REPLACE INTO metadata (key, value) VALUES (?1, datetime('now'))",
+            params![BUILT_AT_KEY],
+        )?;
+        drop(stmt);
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The recorded embedding model, if a build has recorded one (older knowledge bases haven't)
+    pub fn embedding_info(&self) -> Result<Option<EmbeddingInfo>> {
+        let Some(model) = self.metadata(EMBEDDING_MODEL_KEY)? else {
+            return Ok(None);
+        };
+        Ok(Some(EmbeddingInfo {
+            model,
+            dimension: self.metadata(EMBEDDING_DIMENSION_KEY)?.and_then(|d| d.parse().ok()),
+            built_at: self.metadata(BUILT_AT_KEY)?,
+        }))
+    }
+
+    /// The recorded embedding info when it names a model other than `model`: the stored
+    /// vectors can't be compared with that model's query embeddings
+    pub fn embedding_mismatch(&self, model: &str) -> Result<Option<EmbeddingInfo>> {
+        Ok(self.embedding_info()?.filter(|info| info.model != model))
+    }
+
+    /// Re-encode stored embeddings that aren't in this store's encoding (after the
+    /// configured encoding changed), returning how many were converted
+    pub fn convert_embeddings(&self) -> Result<usize> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        let rows: Vec<(i64, Vec<u8>, i64, String)> = tx
+            .prepare("SELECT id, embedding, dimension, encoding FROM commands WHERE encoding != ?1")?
+            .query_map(params![self.encoding.as_str()], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)))?
+            .collect::<Result<_, _>>()?;
+        for (id, bytes, dimension, encoding) in &rows {
+            let embedding = embedding_codec::decode(EmbeddingEncoding::parse(encoding)?, *dimension as usize, bytes)?;
+            tx.execute(
+                "UPDATE commands SET embedding = ?2, encoding = ?3 WHERE id = ?1",
+                params![id, embedding_codec::encode(self.encoding, &embedding), self.encoding.as_str()],
+            )?;
+        }
+        tx.commit()?;
+        drop(conn);
+        if !rows.is_empty() {
+            self.invalidate();
+        }
+        Ok(rows.len())
+    }
+
+    /// Record the commands a build couldn't read, replacing what an earlier attempt at
+    /// the same run recorded
+    pub fn record_build_problems(&self, run_id: i64, problems: &[ExtractionProblem]) -> Result<()> {
+        let mut conn = self.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM build_problems WHERE run_id = ?1", params![run_id])?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO build_problems (run_id, package_name, command_name, timed_out, detail)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            for p in problems {
+                stmt.execute(params![run_id, p.package_name, p.command_name, p.timed_out, p.detail])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The problems recorded by the most recent build
+    pub fn latest_build_problems(&self) -> Result<Vec<ExtractionProblem>> {
+        let conn = self.conn()?;
+        let mut stmt = conn.prepare(
+            "SELECT package_name, command_name, timed_out, detail FROM build_problems
+             WHERE run_id = (SELECT MAX(id) FROM build_runs)
+             ORDER BY package_name, command_name",
+        )?;
+        let problems = stmt
+            .query_map([], |row| {
+                Ok(ExtractionProblem {
+                    package_name: row.get(0)?,
+                    command_name: row.get(1)?,
+                    timed_out: row.get(2)?,
+                    detail: row.get(3)?,
+                })
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(problems)
+    }
+
+    /// Write a consistent copy of the database to `path`, replacing anything there,
+    /// for a build to update without touching this store (see `swap_in`)
+    pub fn snapshot_to(&self, path: &Path) -> Result<()> {
+        remove_store_files(path);
+        self.conn()?.execute("VACUUM INTO ?1", params![path.to_string_lossy()])?;
+        Ok(())
+    }
+
+    /// Replace this store's contents, and its search index, with the database at
+    /// `shadow_path`. The copy is one transaction, so readers see either the old or
+    /// the new knowledge base and an error leaves the old one as it was.
+    pub fn swap_in(&self, shadow_path: &Path) -> Result<()> {
+        let mut conn = self.conn()?;
+        conn.execute("ATTACH DATABASE ?1 AS shadow", params![shadow_path.to_string_lossy()])?;
+        let copied = copy_from_shadow(&mut conn);
+        conn.execute("DETACH DATABASE shadow", [])?;
+        copied?;
+        drop(conn);
+
+        // Until the new index is in place the old one no longer matches and is ignored
+        let shadow_ann = shadow_path.with_extension("hnsw");
+        if shadow_ann.exists() {
+            std::fs::rename(&shadow_ann, &self.ann_path)?;
+        } else {
+            let _ = std::fs::remove_file(&self.ann_path);
+        }
+        self.invalidate();
+        Ok(())
+    }
+}
+
+/// Schema version written by this build of mac-aid (`PRAGMA user_version`)
+pub const SCHEMA_VERSION: i32 = MIGRATIONS.len() as i32;
+
+/// `MIGRATIONS[n]` upgrades a database from schema version `n` to `n + 1`. Append new
+/// migrations here; never edit one that has been released.
+const MIGRATIONS: &[fn(&Transaction) -> Result<()>] = &[migrate_to_v1, migrate_to_v2];
+
+/// Bring the database up to `SCHEMA_VERSION`, one migration per transaction, and
+/// refuse databases written by a newer mac-aid
+fn migrate(conn: &mut Connection, db_path: &Path) -> Result<()> {
+    let check = |version: i32| {
+        if version > SCHEMA_VERSION {
+            anyhow::bail!(
+                "{} was written by a newer mac-aid (schema version {}, this one supports up to {}); upgrade mac-aid or delete the file to rebuild it",
+                db_path.display(),
+                version,
+                SCHEMA_VERSION
+            );
+        }
+        Ok(version)
+    };
+    // Up-to-date databases are opened without taking the write lock
+    if check(schema_version(conn)?)? == SCHEMA_VERSION {
+        return Ok(());
+    }
+    loop {
+        // Immediate transactions keep two processes from running the same migration;
+        // the version is read again in case another one just did
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let version = check(schema_version(&tx)?)?;
+        let Some(migration) = MIGRATIONS.get(version as usize) else {
+            return Ok(());
+        };
+        migration(&tx)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", version + 1))?;
+        tx.commit()?;
+    }
+}
+
+fn schema_version(conn: &Connection) -> Result<i32> {
+    Ok(conn.query_row("PRAGMA user_version", [], |row| row.get(0))?)
+}
+
+/// The schema as it was before versioning. Databases from that time may be at any
+/// stage of it, so every step checks what is already there.
+fn migrate_to_v1(tx: &Transaction) -> Result<()> {
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS commands (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            package_name TEXT NOT NULL,
+            command_name TEXT NOT NULL,
+            man_content TEXT NOT NULL,
+            embedding BLOB NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    // Databases from before man pages were chunked have no section column
+    let has_section: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('commands') WHERE name = 'section')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_section {
+        tx.execute("ALTER TABLE commands ADD COLUMN section TEXT NOT NULL DEFAULT ''", [])?;
+    }
+
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_package ON commands(package_name)",
+        [],
+    )?;
+
+    tx.execute(
+        "CREATE INDEX IF NOT EXISTS idx_command ON commands(command_name)",
+        [],
+    )?;
+
+    // Metadata of the packages the commands came from, replaced on every build
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS packages (
+            name TEXT PRIMARY KEY,
+            kind TEXT NOT NULL,
+            tap TEXT,
+            version TEXT,
+            description TEXT,
+            homepage TEXT,
+            installed_on_request INTEGER NOT NULL DEFAULT 1
+        )",
+        [],
+    )?;
+    let has_source: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('packages') WHERE name = 'source')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_source {
+        tx.execute("ALTER TABLE packages ADD COLUMN source TEXT NOT NULL DEFAULT 'brew'", [])?;
+    }
+
+    // What previous builds indexed, so an update only re-reads packages whose version
+    // changed and only re-embeds commands whose documentation changed
+    tx.execute_batch(
+        "CREATE TABLE IF NOT EXISTS indexed_packages (
+            name TEXT PRIMARY KEY,
+            version TEXT NOT NULL
+         );
+         CREATE TABLE IF NOT EXISTS indexed_commands (
+            package_name TEXT NOT NULL,
+            command_name TEXT NOT NULL,
+            content_hash TEXT NOT NULL,
+            PRIMARY KEY (package_name, command_name)
+         );",
+    )?;
+
+    // Progress of knowledge base builds, kept in the live database so a build that
+    // was interrupted can be found and resumed from its shadow copy
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS build_runs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            status TEXT NOT NULL,
+            full INTEGER NOT NULL DEFAULT 0,
+            docs_done INTEGER NOT NULL DEFAULT 0,
+            docs_total INTEGER NOT NULL DEFAULT 0,
+            last_document TEXT,
+            started_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+
+    // Facts about the knowledge base as a whole, such as the embedding model
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS metadata (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Commands whose documentation couldn't be read during a build (hung or crashed)
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS build_problems (
+            run_id INTEGER NOT NULL,
+            package_name TEXT NOT NULL,
+            command_name TEXT NOT NULL,
+            timed_out INTEGER NOT NULL,
+            detail TEXT NOT NULL
+        )",
+        [],
+    )?;
+
+    // Full-text index over names and man pages for exact tool/flag matches.
+    // It mirrors the commands table (external content), so only the index is stored.
+    let has_fts: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE name = 'commands_fts')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_fts {
+        tx.execute_batch(
+            "CREATE VIRTUAL TABLE commands_fts USING fts5(
+                command_name, man_content, content='commands', content_rowid='id'
+             );
+             INSERT INTO commands_fts(commands_fts) VALUES('rebuild');",
+        )?;
+    }
+
+    Ok(())
 }
 
-/// Calculate cosine similarity between two vectors
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
-    if a.len() != b.len() {
-        return 0.0;
+/// Store embeddings in the format described in `embedding_codec` instead of bincode,
+/// with their dimension and encoding next to them. Existing rows become `f32`.
+fn migrate_to_v2(tx: &Transaction) -> Result<()> {
+    let has_dimension: bool = tx.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info('commands') WHERE name = 'dimension')",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_dimension {
+        tx.execute_batch(
+            "ALTER TABLE commands ADD COLUMN dimension INTEGER NOT NULL DEFAULT 0;
+             ALTER TABLE commands ADD COLUMN encoding TEXT NOT NULL DEFAULT 'f32';",
+        )?;
+    }
+    // Rows without a dimension are still bincode
+    let rows: Vec<(i64, Vec<u8>)> = tx
+        .prepare("SELECT id, embedding FROM commands WHERE dimension = 0")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    let mut update = tx.prepare("UPDATE commands SET embedding = ?2, dimension = ?3 WHERE id = ?1")?;
+    for (id, bytes) in rows {
+        let embedding: Vec<f32> = bincode::deserialize(&bytes)
+            .map_err(|e| anyhow::anyhow!("Unreadable embedding in row {}: {}", id, e))?;
+        update.execute(params![id, embedding_codec::encode(EmbeddingEncoding::F32, &embedding), embedding.len() as i64])?;
     }
+    Ok(())
+}
 
-    let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-    let magnitude_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-    let magnitude_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+/// Copy every table of the attached `shadow` database over this one, in one transaction
+fn copy_from_shadow(conn: &mut Connection) -> Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "DELETE FROM main.commands;
+         INSERT INTO main.commands (id, package_name, command_name, section, man_content, embedding, dimension, encoding, created_at)
+             SELECT id, package_name, command_name, section, man_content, embedding, dimension, encoding, created_at FROM shadow.commands;
+         INSERT INTO main.commands_fts(commands_fts) VALUES('rebuild');
+         DELETE FROM main.packages;
+         INSERT INTO main.packages (name, source, kind, tap, version, description, homepage, installed_on_request)
+             SELECT name, source, kind, tap, version, description, homepage, installed_on_request FROM shadow.packages;
+         DELETE FROM main.indexed_packages;
+         INSERT INTO main.indexed_packages (name, version) SELECT name, version FROM shadow.indexed_packages;
+         DELETE FROM main.indexed_commands;
+         INSERT INTO main.indexed_commands (package_name, command_name, content_hash)
+             SELECT package_name, command_name, content_hash FROM shadow.indexed_commands;
+         DELETE FROM main.metadata;
+         INSERT INTO main.metadata (key, value) SELECT key, value FROM shadow.metadata;",
+    )?;
+    tx.commit()?;
+    Ok(())
+}
 
-    if magnitude_a == 0.0 || magnitude_b == 0.0 {
-        return 0.0;
+/// Delete a database and the files SQLite and the search index keep next to it
+pub fn remove_store_files(db_path: &Path) {
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(suffix);
+        let _ = std::fs::remove_file(PathBuf::from(path));
     }
+    let _ = std::fs::remove_file(db_path.with_extension("hnsw"));
+}
+
+/// Delete the chunks of a package's commands (all of them, or just `command_name`),
+/// their full-text entries and their recorded hashes
+fn delete_commands(tx: &Transaction, package_name: &str, command_name: Option<&str>) -> Result<()> {
+    let rows: Vec<(i64, String, String)> = tx
+        .prepare_cached(
+            "SELECT id, command_name, man_content FROM commands
+             WHERE package_name = ?1 AND (?2 IS NULL OR command_name = ?2)",
+        )?
+        .query_map(params![package_name, command_name], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+        .collect::<Result<_, _>>()?;
+    // External-content FTS tables need the old values to remove a row
+    for (id, command, content) in &rows {
+        tx.execute(
+            "INSERT INTO commands_fts (commands_fts, rowid, command_name, man_content) VALUES ('delete', ?1, ?2, ?3)",
+            params![id, command, content],
+        )?;
+    }
+    tx.execute(
+        "DELETE FROM commands WHERE package_name = ?1 AND (?2 IS NULL OR command_name = ?2)",
+        params![package_name, command_name],
+    )?;
+    tx.execute(
+        "DELETE FROM indexed_commands WHERE package_name = ?1 AND (?2 IS NULL OR command_name = ?2)",
+        params![package_name, command_name],
+    )?;
+    Ok(())
+}
+
+/// Turn free text into an FTS5 query matching any of its words (BM25 ranks pages with
+/// more and rarer words first), quoting each one so user input can never be parsed as
+/// FTS syntax. None if there are no words.
+fn fts_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split(|c: char| !c.is_alphanumeric() && c != '_')
+        .filter(|t| !t.is_empty())
+        .map(|t| format!("\"{}\"", t.to_lowercase()))
+        .collect();
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" OR "))
+    }
+}
+
+fn row_to_command(row: &rusqlite::Row) -> rusqlite::Result<StoredCommand> {
+    let embedding_bytes: Vec<u8> = row.get(4)?;
+    let dimension: i64 = row.get(6)?;
+    let encoding: String = row.get(7)?;
+    let embedding = EmbeddingEncoding::parse(&encoding)
+        .and_then(|encoding| embedding_codec::decode(encoding, dimension as usize, &embedding_bytes))
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(
+            4,
+            rusqlite::types::Type::Blob,
+            e.into(),
+        ))?;
 
-    dot_product / (magnitude_a * magnitude_b)
+    Ok(StoredCommand {
+        id: row.get(0)?,
+        package_name: row.get(1)?,
+        command_name: row.get(2)?,
+        section: row.get(5)?,
+        man_content: row.get(3)?,
+        embedding,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::time::{SystemTime, UNIX_EPOCH};
+    use crate::test_support::TempDir;
 
-    fn temp_db_path() -> PathBuf {
-        let mut p = std::env::temp_dir();
-        let nanos = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos();
-        p.push(format!("mac_aid_test_{}.db", nanos));
-        p
+    /// Insert one chunk directly, without the bookkeeping of `replace_command`, so
+    /// tests can store several chunks of a command one at a time
+    fn store_command(
+        vs: &VectorStore,
+        package_name: &str,
+        command_name: &str,
+        section: &str,
+        man_content: &str,
+        embedding: &[f32],
+    ) -> Result<i64> {
+        let embedding_bytes = embedding_codec::encode(vs.encoding, embedding);
+
+        let mut conn = vs.conn()?;
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT INTO commands (package_name, command_name, section, man_content, embedding, dimension, encoding)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                package_name,
+                command_name,
+                section,
+                man_content,
+                embedding_bytes,
+                embedding.len() as i64,
+                vs.encoding.as_str()
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+        tx.execute(
+            "INSERT INTO commands_fts (rowid, command_name, man_content) VALUES (?1, ?2, ?3)",
+            params![id, command_name, man_content],
+        )?;
+        tx.commit()?;
+        drop(conn);
+
+        vs.invalidate();
+        Ok(id)
+    }
+
+    #[test]
+    fn test_store_and_get() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let vs = VectorStore::new(path).unwrap();
+
+        let id1 = store_command(&vs, "pkg", "cmd", "", "man", &[0.1, 0.2, 0.3])
+            .unwrap();
+        let id2 = store_command(&vs, "pkg2", "cmd2", "", "man2", &[0.0, 1.0, 0.0])
+            .unwrap();
+
+        assert_eq!(vs.command_count().unwrap(), 2);
+        let e1 = vs.get_command(id1).unwrap().unwrap().embedding;
+        assert_eq!(e1, vec![0.1, 0.2, 0.3]);
+        let e2 = vs.get_command(id2).unwrap().unwrap().embedding;
+        assert_eq!(e2, vec![0.0, 1.0, 0.0]);
     }
 
     #[test]
-    fn test_cosine_similarity_basic() {
-        let a = vec![1.0, 0.0];
-        let b = vec![1.0, 0.0];
-        let s = cosine_similarity(&a, &b);
-        assert!((s - 1.0).abs() < 1e-6);
+    fn test_search_similar_ordering() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let vs = VectorStore::new(path).unwrap();
+        store_command(&vs, "p1", "c1", "", "m", &[1.0, 0.0]).unwrap();
+        store_command(&vs, "p2", "c2", "", "m", &[0.0, 1.0]).unwrap();
+
+        let res = vs.search_similar(&[0.9, 0.1], 1, 0.0).unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].1.command_name, "c1");
+        assert!(res[0].0 > 0.9);
     }
 
     #[test]
-    fn test_cosine_similarity_orthogonal() {
-        let a = vec![1.0, 0.0];
-        let b = vec![0.0, 1.0];
-        let s = cosine_similarity(&a, &b);
-        assert!((s - 0.0).abs() < 1e-6);
+    fn test_search_similar_applies_min_similarity() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let vs = VectorStore::new(path).unwrap();
+        store_command(&vs, "p1", "c1", "", "m", &[1.0, 0.0]).unwrap();
+        store_command(&vs, "p2", "c2", "", "m", &[0.0, 1.0]).unwrap();
+
+        let res = vs.search_similar(&[1.0, 0.1], 2, 0.5).unwrap();
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].1.command_name, "c1");
+        assert!(vs.search_similar(&[-1.0, -1.0], 2, 0.5).unwrap().is_empty());
+        assert_eq!(vs.search_similar(&[-1.0, -1.0], 2, -1.0).unwrap().len(), 2);
     }
 
     #[test]
-    fn test_cosine_similarity_mismatch_len() {
-        let a = vec![1.0, 0.0, 0.0];
-        let b = vec![1.0, 0.0];
-        let s = cosine_similarity(&a, &b);
-        assert!((s - 0.0).abs() < 1e-6);
+    fn test_search_lexical_matches_names_and_flags() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let vs = VectorStore::new(path).unwrap();
+        store_command(&vs, "rsync", "rsync", "", "rsync - a fast file copying tool\n--delete  delete extraneous files", &[1.0, 0.0]).unwrap();
+        store_command(&vs, "jq", "jq", "", "jq - command-line JSON processor\n-r  output raw strings", &[0.0, 1.0]).unwrap();
+        store_command(&vs, "coreutils", "cp", "", "cp - copy files; see rsync for remote copies", &[0.5, 0.5]).unwrap();
+
+        // Any word may match; pages matching more of them rank first
+        let res = vs.search_lexical("rsync --delete", 5).unwrap();
+        assert_eq!(res.iter().map(|c| c.command_name.as_str()).collect::<Vec<_>>(), vec!["rsync", "cp"]);
+        assert_eq!(vs.search_lexical("how do I delete extraneous files", 5).unwrap()[0].command_name, "rsync");
+
+        // The tool's own page outranks one that merely mentions it
+        let res = vs.search_lexical("rsync", 5).unwrap();
+        assert_eq!(res.iter().map(|c| c.command_name.as_str()).collect::<Vec<_>>(), vec!["rsync", "cp"]);
+
+        assert_eq!(vs.search_lexical("jq -r", 5).unwrap()[0].command_name, "jq");
+        assert!(vs.search_lexical("\"unbalanced OR (", 5).unwrap().is_empty());
+        assert!(vs.search_lexical("--", 5).unwrap().is_empty());
+
+        vs.remove_packages_except(&HashSet::new()).unwrap();
+        assert!(vs.search_lexical("rsync", 5).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_fts_index_built_for_existing_rows() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        {
+            let vs = VectorStore::new(path.clone()).unwrap();
+            store_command(&vs, "git", "git", "", "git - the stupid content tracker", &[1.0]).unwrap();
+            // As in a database from before full-text search (and schema versions)
+            vs.conn().unwrap().execute_batch("DROP TABLE commands_fts; PRAGMA user_version = 0").unwrap();
+        }
+        let vs = VectorStore::new(path).unwrap();
+        assert_eq!(vs.search_lexical("tracker", 5).unwrap()[0].command_name, "git");
     }
 
     #[test]
-    fn test_store_and_get_all() {
-        let path = temp_db_path();
+    fn test_chunks_keep_section_and_parent_command() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
         let vs = VectorStore::new(path).unwrap();
+        store_command(&vs, "rsync", "rsync", "NAME", "rsync - a fast file copying tool", &[1.0, 0.0]).unwrap();
+        let id = store_command(&vs, "rsync", "rsync", "OPTIONS --delete", "--delete ...", &[0.0, 1.0]).unwrap();
+        store_command(&vs, "jq", "jq", "NAME", "jq - JSON processor", &[0.5, 0.5]).unwrap();
 
-        let id1 = vs
-            .store_command("pkg", "cmd", "man", &[0.1, 0.2, 0.3])
+        assert_eq!(vs.count().unwrap(), 3);
+        assert_eq!(vs.command_count().unwrap(), 2);
+        let cmd = vs.get_command(id).unwrap().unwrap();
+        assert_eq!((cmd.command_name.as_str(), cmd.section.as_str()), ("rsync", "OPTIONS --delete"));
+    }
+
+    #[test]
+    fn test_section_column_added_to_old_database() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE commands (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    package_name TEXT NOT NULL,
+                    command_name TEXT NOT NULL,
+                    man_content TEXT NOT NULL,
+                    embedding BLOB NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                )",
+            )
             .unwrap();
-        let id2 = vs
-            .store_command("pkg2", "cmd2", "man2", &[0.0, 1.0, 0.0])
+            let bytes = bincode::serialize(&vec![1.0f32, 0.0]).unwrap();
+            conn.execute(
+                "INSERT INTO commands (package_name, command_name, man_content, embedding) VALUES ('p', 'c', 'm', ?1)",
+                params![bytes],
+            )
             .unwrap();
-
-        let all = vs.get_all_commands().unwrap();
-        assert_eq!(all.len(), 2);
-        let e1 = all.iter().find(|c| c.id == id1).unwrap().embedding.clone();
-        assert_eq!(e1, vec![0.1, 0.2, 0.3]);
-        let e2 = all.iter().find(|c| c.id == id2).unwrap().embedding.clone();
-        assert_eq!(e2, vec![0.0, 1.0, 0.0]);
+        }
+        let vs = VectorStore::new(path).unwrap();
+        let res = vs.search_similar(&[1.0, 0.0], 1, 0.0).unwrap();
+        assert_eq!((res[0].1.command_name.as_str(), res[0].1.section.as_str()), ("c", ""));
     }
 
     #[test]
-    fn test_search_similar_ordering() {
-        let path = temp_db_path();
+    fn test_store_packages_replaces_metadata() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
         let vs = VectorStore::new(path).unwrap();
-        vs.store_command("p1", "c1", "m", &[1.0, 0.0]).unwrap();
-        vs.store_command("p2", "c2", "m", &[0.0, 1.0]).unwrap();
+        let package = |name: &str, desc: Option<&str>| Package {
+            version: Some("1.0".to_string()),
+            desc: desc.map(str::to_string),
+            ..Package::new("brew", name.to_string())
+        };
 
-        let res = vs.search_similar(&[0.9, 0.1], 1).unwrap();
-        assert_eq!(res.len(), 1);
-        assert_eq!(res[0].command_name, "c1");
+        vs.store_packages(&[package("jq", Some("JSON processor")), package("old", Some("gone"))]).unwrap();
+        vs.store_packages(&[package("jq", Some("JSON processor")), package("zlib", None)]).unwrap();
+        let descriptions = vs.package_descriptions().unwrap();
+        assert_eq!(descriptions.len(), 1);
+        assert_eq!(descriptions["jq"], "JSON processor");
     }
 
     #[test]
-    fn test_clear_and_is_empty() {
-        let path = temp_db_path();
-        let mut vs = VectorStore::new(path).unwrap();
-        vs.store_command("p", "c", "m", &[0.1, 0.2]).unwrap();
+    fn test_remove_all_packages_and_is_empty() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let vs = VectorStore::new(path).unwrap();
+        store_command(&vs, "p", "c", "", "m", &[0.1, 0.2]).unwrap();
         assert!(!vs.is_empty().unwrap());
-        vs.clear().unwrap();
+        assert_eq!(vs.remove_packages_except(&HashSet::new()).unwrap(), 1);
+        assert!(vs.is_empty().unwrap());
+    }
+
+    #[test]
+    fn test_incremental_updates_replace_and_remove_commands() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let vs = VectorStore::new(path).unwrap();
+        let chunk = |section: &str, content: &str| Chunk { section: section.into(), content: content.into() };
+
+        vs.replace_command("jq", "jq", "h1", &[(chunk("NAME", "jq - JSON processor"), vec![1.0, 0.0])]).unwrap();
+        vs.replace_command("ripgrep", "rg", "h2", &[(chunk("NAME", "rg - recursive grep"), vec![0.0, 1.0])]).unwrap();
+        vs.mark_package_indexed("jq", "1.7").unwrap();
+        assert_eq!(vs.indexed_versions().unwrap().get("jq").map(String::as_str), Some("1.7"));
+
+        // Replacing drops the old chunks and their full-text entries
+        let new = [(chunk("NAME", "jq - command-line JSON"), vec![1.0, 0.0]), (chunk("OPTIONS -r", "-r raw output"), vec![0.7, 0.7])];
+        vs.replace_command("jq", "jq", "h3", &new).unwrap();
+        assert_eq!(vs.count().unwrap(), 3);
+        assert!(vs.search_lexical("processor", 5).unwrap().is_empty());
+        assert_eq!(vs.search_lexical("raw", 5).unwrap()[0].section, "OPTIONS -r");
+        assert_eq!(vs.command_hashes().unwrap()[&("jq".to_string(), "jq".to_string())], "h3");
+
+        assert_eq!(vs.remove_commands_except("jq", &HashSet::new()).unwrap(), 1);
+        assert_eq!(vs.count().unwrap(), 1);
+
+        let installed: HashSet<&str> = ["jq"].into_iter().collect();
+        assert_eq!(vs.remove_packages_except(&installed).unwrap(), 1);
         assert!(vs.is_empty().unwrap());
+        assert!(vs.search_lexical("grep", 5).unwrap().is_empty());
+        assert!(vs.command_hashes().unwrap().is_empty());
+        assert!(vs.indexed_versions().unwrap().contains_key("jq"));
+    }
+
+    #[test]
+    fn test_build_runs_track_incomplete_builds() {
+        let dir = TempDir::new("store");
+        let vs = VectorStore::new(dir.join("commands.db")).unwrap();
+        assert_eq!(vs.incomplete_build_run().unwrap(), None);
+
+        let first = vs.start_build_run(true).unwrap();
+        vs.checkpoint_build_run(first, 3, 10, "jq/jq").unwrap();
+        let run = vs.incomplete_build_run().unwrap().unwrap();
+        assert_eq!((run.id, run.status.as_str(), run.full), (first, "running", true));
+        assert_eq!((run.docs_done, run.docs_total), (3, 10));
+        assert_eq!(run.last_document.as_deref(), Some("jq/jq"));
+
+        // A new build supersedes the unfinished one
+        let second = vs.start_build_run(false).unwrap();
+        vs.set_build_run_status(second, "failed").unwrap();
+        assert_eq!(vs.incomplete_build_run().unwrap().unwrap().id, second);
+        vs.set_build_run_status(second, "complete").unwrap();
+        assert_eq!(vs.incomplete_build_run().unwrap(), None);
+    }
+
+    #[test]
+    fn test_new_database_is_at_current_schema_version() {
+        let dir = TempDir::new("store");
+        let vs = VectorStore::new(dir.join("commands.db")).unwrap();
+        assert_eq!(schema_version(&vs.conn().unwrap()).unwrap(), SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn test_unversioned_database_is_migrated_in_place() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        {
+            // A database from before chunking: no section column, no other tables
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE commands (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    package_name TEXT NOT NULL,
+                    command_name TEXT NOT NULL,
+                    man_content TEXT NOT NULL,
+                    embedding BLOB NOT NULL,
+                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+                 );",
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO commands (package_name, command_name, man_content, embedding) VALUES ('jq', 'jq', 'jq page', ?1)",
+                params![bincode::serialize(&vec![1.0f32, 0.0]).unwrap()],
+            )
+            .unwrap();
+        }
+
+        let vs = VectorStore::new(path.clone()).unwrap();
+        assert_eq!(schema_version(&vs.conn().unwrap()).unwrap(), SCHEMA_VERSION);
+        assert_eq!(vs.count().unwrap(), 1);
+        let cmd = &vs.search_lexical("jq", 5).unwrap()[0];
+        assert_eq!(cmd.section, "");
+        // The bincode embedding was rewritten as raw f32
+        assert_eq!(cmd.embedding, vec![1.0, 0.0]);
+        assert_eq!(vs.search_similar(&[1.0, 0.0], 1, 0.5).unwrap().len(), 1);
+        drop(vs);
+        // Opening it again finds nothing to do
+        assert_eq!(VectorStore::new(path).unwrap().count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_quantized_embeddings_are_searched_and_converted() {
+        let dir = TempDir::new("store");
+        let mut vs = VectorStore::new(dir.join("commands.db")).unwrap();
+        let f32_id = store_command(&vs, "p", "exact", "", "m", &[1.0, 0.0, 0.0]).unwrap();
+        vs.set_embedding_encoding(EmbeddingEncoding::Int8);
+        store_command(&vs, "p", "close", "", "m", &[0.9, 0.1, 0.0]).unwrap();
+        store_command(&vs, "p", "far", "", "m", &[0.0, 0.0, 1.0]).unwrap();
+
+        // The older f32 row is re-encoded; everything is then searched as int8
+        assert_eq!(vs.convert_embeddings().unwrap(), 1);
+        assert_eq!(vs.convert_embeddings().unwrap(), 0);
+        let hits = vs.search_similar(&[1.0, 0.05, 0.0], 3, 0.5).unwrap();
+        let names: Vec<&str> = hits.iter().map(|(_, c)| c.command_name.as_str()).collect();
+        assert_eq!(names, vec!["exact", "close"]);
+        let stored = vs.get_command(f32_id).unwrap().unwrap().embedding;
+        assert!((stored[0] - 1.0).abs() < 0.01 && stored[1].abs() < 0.01);
+
+        let blob_len: i64 = vs
+            .conn()
+            .unwrap()
+            .query_row("SELECT length(embedding) FROM commands WHERE id = ?1", params![f32_id], |row| row.get(0))
+            .unwrap();
+        assert_eq!(blob_len, 4 + 3);
+    }
+
+    #[test]
+    fn test_database_from_newer_version_is_refused() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        drop(VectorStore::new(path.clone()).unwrap());
+        Connection::open(&path)
+            .unwrap()
+            .execute_batch(&format!("PRAGMA user_version = {}", SCHEMA_VERSION + 1))
+            .unwrap();
+        let err = VectorStore::new(path).err().expect("newer schema should be refused");
+        assert!(err.to_string().contains("newer mac-aid"));
+    }
+
+    #[test]
+    fn test_embedding_info_detects_model_changes() {
+        let dir = TempDir::new("store");
+        let vs = VectorStore::new(dir.join("commands.db")).unwrap();
+        // Knowledge bases from before the metadata table can't be checked
+        assert_eq!(vs.embedding_info().unwrap(), None);
+        assert_eq!(vs.embedding_mismatch("all-minilm").unwrap(), None);
+
+        vs.set_embedding_model("all-minilm").unwrap();
+        assert_eq!(vs.embedding_info().unwrap().unwrap().dimension, None);
+        vs.record_embedding_info("all-minilm", Some(384)).unwrap();
+        let info = vs.embedding_info().unwrap().unwrap();
+        assert_eq!((info.model.as_str(), info.dimension), ("all-minilm", Some(384)));
+        assert!(info.built_at.is_some());
+
+        assert_eq!(vs.embedding_mismatch("all-minilm").unwrap(), None);
+        assert_eq!(vs.embedding_mismatch("nomic-embed-text").unwrap(), Some(info));
+    }
+
+    #[test]
+    fn test_build_problems_are_kept_for_the_latest_run() {
+        let dir = TempDir::new("store");
+        let vs = VectorStore::new(dir.join("commands.db")).unwrap();
+        let problem = |command: &str| ExtractionProblem {
+            package_name: "pkg".into(),
+            command_name: command.into(),
+            timed_out: true,
+            detail: format!("{} -h timed out", command),
+        };
+        let first = vs.start_build_run(false).unwrap();
+        vs.record_build_problems(first, &[problem("a"), problem("b")]).unwrap();
+        // A resumed attempt replaces what the run recorded before
+        vs.record_build_problems(first, &[problem("b")]).unwrap();
+        assert_eq!(vs.latest_build_problems().unwrap(), vec![problem("b")]);
+
+        vs.start_build_run(false).unwrap();
+        assert!(vs.latest_build_problems().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_swap_in_replaces_contents_atomically() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let live = VectorStore::new(path.clone()).unwrap();
+        store_command(&live, "old", "c", "", "old page", &[1.0, 0.0]).unwrap();
+        let reader = VectorStore::new(path.clone()).unwrap();
+        assert_eq!(reader.count().unwrap(), 1);
+
+        // The shadow starts as a copy; changes to it don't show until the swap
+        let shadow_path = path.with_extension("next.db");
+        live.snapshot_to(&shadow_path).unwrap();
+        let shadow = VectorStore::new(shadow_path.clone()).unwrap();
+        shadow.replace_command("new", "c", "h", &[(Chunk { section: "NAME".into(), content: "new page".into() }, vec![0.0, 1.0])]).unwrap();
+        shadow.remove_packages_except(&["new"].into_iter().collect()).unwrap();
+        shadow.build_ann_index().unwrap();
+        shadow.record_embedding_info("new-model", Some(2)).unwrap();
+        drop(shadow);
+        assert_eq!(reader.search_lexical("old", 5).unwrap().len(), 1);
+
+        live.swap_in(&shadow_path).unwrap();
+        remove_store_files(&shadow_path);
+        assert_eq!(reader.count().unwrap(), 1);
+        assert!(reader.search_lexical("old", 5).unwrap().is_empty());
+        assert_eq!(reader.search_lexical("new", 5).unwrap()[0].package_name, "new");
+        assert_eq!(reader.embedding_info().unwrap().unwrap().model, "new-model");
+        assert!(path.with_extension("hnsw").exists());
+        assert!(!shadow_path.exists());
+    }
+
+    #[test]
+    fn test_ann_index_search_matches_exact() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let vs = VectorStore::new(path).unwrap();
+        for i in 0..50 {
+            let angle = i as f32 * 0.1;
+            store_command(&vs, "p", &format!("c{}", i), "", "m", &[angle.cos(), angle.sin(), 0.1]).unwrap();
+        }
+        assert_eq!(vs.build_ann_index().unwrap(), 50);
+
+        let query = [0.5f32.cos(), 0.5f32.sin(), 0.1];
+        let resident = vs.resident().unwrap();
+        let index = resident.ann.as_ref().expect("index should load");
+        let approx: Vec<i64> = index.search(&query, 3, 64).into_iter().map(|(_, id)| id).collect();
+        let exact: Vec<i64> = resident.matrix.search(&query, 3).into_iter().map(|(_, id)| id).collect();
+        assert_eq!(approx, exact);
+    }
+
+    #[test]
+    fn test_stale_ann_index_is_ignored() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let vs = VectorStore::new(path.clone()).unwrap();
+        store_command(&vs, "p", "c1", "", "m", &[1.0, 0.0]).unwrap();
+        vs.build_ann_index().unwrap();
+        store_command(&vs, "p", "c2", "", "m", &[0.0, 1.0]).unwrap();
+
+        let reopened = VectorStore::new(path).unwrap();
+        assert!(reopened.resident().unwrap().ann.is_none());
+    }
+
+    #[test]
+    fn test_resident_embeddings_refresh_after_external_writes() {
+        let dir = TempDir::new("store");
+        let path = dir.join("commands.db");
+        let reader = VectorStore::new(path.clone()).unwrap();
+        let writer = VectorStore::new(path).unwrap();
+        store_command(&writer, "p1", "c1", "", "m", &[1.0, 0.0]).unwrap();
+        assert_eq!(reader.search_similar(&[0.0, 1.0], 1, 0.0).unwrap()[0].1.command_name, "c1");
+
+        // Written through another connection: invisible until reload
+        store_command(&writer, "p2", "c2", "", "m", &[0.0, 1.0]).unwrap();
+        assert_eq!(reader.search_similar(&[0.0, 1.0], 1, 0.0).unwrap()[0].1.command_name, "c1");
+        reader.reload().unwrap();
+        assert_eq!(reader.search_similar(&[0.0, 1.0], 1, 0.0).unwrap()[0].1.command_name, "c2");
     }
 }